@@ -0,0 +1,165 @@
+//! Expands a subset of the mhchem `\ce{...}` notation into plain LaTeX,
+//! which `latex2mathml` can convert.
+//!
+//! The supported subset is:
+//! - element symbols, e.g. `\ce{NaCl}`,
+//! - digits following a symbol or closing bracket become subscripts, e.g. `\ce{H2O}`,
+//!   while leading digits are kept as coefficients, e.g. `\ce{2H2}`,
+//! - charges, either explicit, e.g. `\ce{SO4^2-}` or `\ce{Fe^{3+}}`,
+//!   or a trailing `+`/`-` after a species, e.g. `\ce{Na+}`,
+//! - the arrows `->`, `<-`, `<->` and `<=>`.
+//!
+//! Everything else, such as `+` between species and states like `(aq)`,
+//! is passed through.
+
+use std::borrow::Cow;
+
+use anyhow::{anyhow, Result};
+
+const ARROWS: [(&str, &str); 4] = [
+    ("<=>", r"\rightleftharpoons"),
+    ("<->", r"\leftrightarrow"),
+    ("->", r"\rightarrow"),
+    ("<-", r"\leftarrow"),
+];
+
+/// Replaces every `\ce{...}` in the LaTeX with its expansion.
+pub fn expand_chemistry(latex: &str) -> Result<Cow<'_, str>> {
+    const CE: &str = r"\ce{";
+    if !latex.contains(CE) {
+        return Ok(Cow::Borrowed(latex));
+    }
+    let mut out = String::with_capacity(latex.len());
+    let mut rest = latex;
+    while let Some(start) = rest.find(CE) {
+        out += &rest[..start];
+        rest = &rest[start + CE.len()..];
+        let end = closing_brace(rest).ok_or_else(|| anyhow!("Unclosed \\ce in '{latex}'"))?;
+        out += "{";
+        out += &expand_formula(&rest[..end]);
+        out += "}";
+        rest = &rest[end + 1..];
+    }
+    out += rest;
+    Ok(Cow::Owned(out))
+}
+
+/// Returns the position of the brace closing an already opened group.
+fn closing_brace(latex: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (idx, c) in latex.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(idx),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn expand_formula(formula: &str) -> String {
+    let mut out = String::with_capacity(formula.len() * 2);
+    // Whether the previous token can take a subscript or charge.
+    let mut after_species = false;
+    let mut rest = formula;
+    while let Some(c) = rest.chars().next() {
+        if let Some((arrow, command)) = ARROWS.iter().find(|(arrow, _)| rest.starts_with(arrow)) {
+            out += " ";
+            out += command;
+            out += " ";
+            rest = &rest[arrow.len()..];
+            after_species = false;
+            continue;
+        }
+        let len = if c.is_ascii_alphabetic() {
+            let len = run_length(rest, |c| c.is_ascii_alphabetic());
+            out += r"\text{";
+            out += &rest[..len];
+            out += "}";
+            after_species = true;
+            len
+        } else if c.is_ascii_digit() {
+            let len = run_length(rest, |c| c.is_ascii_digit());
+            if after_species {
+                out += "_{";
+                out += &rest[..len];
+                out += "}";
+            } else {
+                out += &rest[..len];
+            }
+            len
+        } else if c == '^' {
+            let charge = &rest[1..];
+            let (charge, len) = match charge.strip_prefix('{') {
+                Some(inner) => match closing_brace(inner) {
+                    Some(end) => (&inner[..end], end + 3),
+                    None => (inner, rest.len()),
+                },
+                None => {
+                    let len = run_length(charge, |c| c.is_ascii_digit() || c == '+' || c == '-');
+                    (&charge[..len], len + 1)
+                }
+            };
+            out += "{}^{";
+            out += charge;
+            out += "}";
+            after_species = false;
+            len
+        } else if (c == '+' || c == '-')
+            && after_species
+            && rest[1..]
+                .chars()
+                .next()
+                .is_none_or(|next| next.is_whitespace() || next == ')')
+        {
+            out += "{}^{";
+            out.push(c);
+            out += "}";
+            after_species = false;
+            1
+        } else {
+            out.push(c);
+            after_species = c == ')' || c == ']';
+            c.len_utf8()
+        };
+        rest = &rest[len..];
+    }
+    out
+}
+
+fn run_length(s: &str, pred: impl Fn(char) -> bool) -> usize {
+    s.find(|c| !pred(c)).unwrap_or(s.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formulas() -> Result<()> {
+        assert_eq!(expand_chemistry(r"\ce{H2O}")?, r"{\text{H}_{2}\text{O}}");
+        assert_eq!(expand_chemistry(r"\ce{CO2}")?, r"{\text{CO}_{2}}");
+        assert_eq!(
+            expand_chemistry(r"\ce{SO4^2-} + \ce{Na+}")?,
+            r"{\text{SO}_{4}{}^{2-}} + {\text{Na}{}^{+}}"
+        );
+        assert_eq!(expand_chemistry(r"\ce{Fe^{3+}}")?, r"{\text{Fe}{}^{3+}}");
+        assert_eq!(expand_chemistry("x^2")?, Cow::Borrowed("x^2"));
+        assert!(expand_chemistry(r"\ce{H2O").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn reactions() -> Result<()> {
+        assert_eq!(
+            expand_chemistry(r"\ce{2H2 + O2 -> 2H2O}")?,
+            r"{2\text{H}_{2} + \text{O}_{2}  \rightarrow  2\text{H}_{2}\text{O}}"
+        );
+        assert_eq!(
+            expand_chemistry(r"\ce{Ca(OH)2 <=> Ca^2+ + 2OH-}")?,
+            r"{\text{Ca}(\text{OH})_{2}  \rightleftharpoons  \text{Ca}{}^{2+} + 2\text{OH}{}^{-}}"
+        );
+        Ok(())
+    }
+}
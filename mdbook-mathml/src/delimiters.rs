@@ -0,0 +1,149 @@
+//! Rewrites maths written with other delimiters, e.g. `\(a\)` or `\[b\]`,
+//! to the dollar delimiters pulldown-cmark recognises.
+//!
+//! Delimiters in code, raw HTML or existing dollar maths are left alone,
+//! as are escaped delimiters, e.g. `\\(`.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+/// A pair of delimiters surrounding maths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delimiter {
+    pub open: String,
+    pub close: String,
+    /// Whether the maths is displayed as a block, like `$$...$$`,
+    /// rather than inline, like `$...$`.
+    pub display: bool,
+}
+
+pub fn normalize_delimiters<'a>(
+    markdown: &'a str,
+    delimiters: &[Delimiter],
+    extensions: Options,
+) -> Cow<'a, str> {
+    if !delimiters
+        .iter()
+        .any(|delimiter| markdown.contains(&delimiter.open))
+    {
+        return Cow::Borrowed(markdown);
+    }
+    let protected = protected_ranges(markdown, extensions);
+    let is_protected = |idx: usize| protected.iter().any(|range| range.contains(&idx));
+
+    let mut out = String::with_capacity(markdown.len());
+    let mut cursor = 0;
+    let mut idx = 0;
+    while idx < markdown.len() {
+        let rest = &markdown[idx..];
+        let found = delimiters.iter().find_map(|delimiter| {
+            if !rest.starts_with(&delimiter.open) || is_protected(idx) || is_escaped(markdown, idx)
+            {
+                return None;
+            }
+            let start = idx + delimiter.open.len();
+            let end = find_close(markdown, start, &delimiter.close, is_protected)?;
+            Some((delimiter, start..end))
+        });
+        let Some((delimiter, latex)) = found else {
+            idx += rest.chars().next().map_or(1, char::len_utf8);
+            continue;
+        };
+        let dollars = if delimiter.display { "$$" } else { "$" };
+        out += &markdown[cursor..idx];
+        out += dollars;
+        out += markdown[latex.clone()].trim();
+        out += dollars;
+        idx = latex.end + delimiter.close.len();
+        cursor = idx;
+    }
+    if cursor == 0 {
+        return Cow::Borrowed(markdown);
+    }
+    out += &markdown[cursor..];
+    Cow::Owned(out)
+}
+
+/// Returns the position of the first unescaped, unprotected closing delimiter.
+fn find_close(
+    markdown: &str,
+    start: usize,
+    close: &str,
+    is_protected: impl Fn(usize) -> bool,
+) -> Option<usize> {
+    let mut from = start;
+    while let Some(offset) = markdown[from..].find(close) {
+        let idx = from + offset;
+        if is_protected(idx) {
+            return None;
+        }
+        if !is_escaped(markdown, idx) {
+            return Some(idx);
+        }
+        from = idx + close.len();
+    }
+    None
+}
+
+/// Whether the character at `idx` is preceded by an odd number of backslashes.
+/// A delimiter starting with a backslash, e.g. `\(`, is escaped by another, e.g. `\\(`.
+fn is_escaped(markdown: &str, idx: usize) -> bool {
+    let backslashes = markdown[..idx]
+        .bytes()
+        .rev()
+        .take_while(|b| *b == b'\\')
+        .count();
+    backslashes % 2 == 1
+}
+
+/// The ranges of code, raw HTML and dollar maths.
+fn protected_ranges(markdown: &str, extensions: Options) -> Vec<Range<usize>> {
+    Parser::new_ext(markdown, extensions)
+        .into_offset_iter()
+        .filter(|(event, _)| {
+            matches!(
+                event,
+                Event::Code(_)
+                    | Event::Html(_)
+                    | Event::InlineHtml(_)
+                    | Event::InlineMath(_)
+                    | Event::DisplayMath(_)
+                    | Event::Start(Tag::CodeBlock(_) | Tag::HtmlBlock)
+            )
+        })
+        .map(|(_, range)| range)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn latex_delimiters() -> Vec<Delimiter> {
+        vec![
+            Delimiter {
+                open: r"\(".to_string(),
+                close: r"\)".to_string(),
+                display: false,
+            },
+            Delimiter {
+                open: r"\[".to_string(),
+                close: r"\]".to_string(),
+                display: true,
+            },
+        ]
+    }
+
+    #[test]
+    fn delimiters() {
+        let normalize =
+            |markdown| normalize_delimiters(markdown, &latex_delimiters(), Options::ENABLE_MATH);
+        assert_eq!(normalize(r"So \(a\) and \[ b \]."), "So $a$ and $$b$$.");
+        assert_eq!(normalize("\\[\nc = d\n\\]\n"), "$$c = d$$\n");
+        assert_eq!(normalize(r"Not \\(a\\) or \(b"), r"Not \\(a\\) or \(b");
+        assert_eq!(normalize(r"`\(a\)` and $\(b\)$"), r"`\(a\)` and $\(b\)$");
+        assert_eq!(normalize("```\n\\(a\\)\n```\n"), "```\n\\(a\\)\n```\n");
+    }
+}
@@ -1,13 +1,26 @@
+mod chemistry;
+mod delimiters;
+
 use std::borrow::Cow;
+use std::ops::Range;
+use std::path::Path;
 use std::{io, process};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use clap::{Arg, Command};
 use latex2mathml::{latex_to_mathml, DisplayStyle};
-use mdbook::book::{Book, BookItem};
+use mdbook::book::{Book, BookItem, Chapter};
 use mdbook::preprocess::{CmdPreprocessor, Preprocessor, PreprocessorContext};
-use pulldown_cmark::{Event, Options, Parser};
+use mdbook::Config;
+use pulldown_cmark::{CowStr, Event, Options, Parser};
+use rayon::prelude::*;
 use semver::{Version, VersionReq};
+use toml::value::{Table, Value};
+
+use chemistry::expand_chemistry;
+use delimiters::{normalize_delimiters, Delimiter};
+
+const NAME: &str = "mathml";
 
 pub fn cli() -> Command {
     Command::new("mdbook-mathml")
@@ -20,18 +33,25 @@ pub fn cli() -> Command {
 }
 
 fn main() -> Result<()> {
-    let preprocessor = MathMlPreprocessor;
-
     let args = cli().get_matches();
 
     if let Some(sub_args) = args.subcommand_matches("supports") {
+        // mdbook doesn't pass the config to the `supports` command,
+        // so read it from the book in the current directory, if there is one.
+        let book_toml = Path::new("book.toml");
+        let config = if book_toml.is_file() {
+            Config::from_disk(book_toml)?
+        } else {
+            Config::default()
+        };
+        let preprocessor = MathMlPreprocessor::new(&config)?;
         let renderer = sub_args
             .get_one::<String>("renderer")
             .expect("Required argument");
         let supported = preprocessor.supports_renderer(renderer);
         process::exit(if supported { 0 } else { 1 });
     }
-    handle_preprocessing(&preprocessor)
+    handle_preprocessing(&MathMlPreprocessor::default())
 }
 
 fn handle_preprocessing(pre: &impl Preprocessor) -> Result<()> {
@@ -56,66 +76,445 @@ fn handle_preprocessing(pre: &impl Preprocessor) -> Result<()> {
     Ok(())
 }
 
-pub struct MathMlPreprocessor;
+#[derive(Default)]
+pub struct MathMlPreprocessor {
+    /// Renderers supported in addition to `html`.
+    renderers: Vec<String>,
+}
+
+impl MathMlPreprocessor {
+    pub fn new(config: &Config) -> Result<Self> {
+        let mut preprocessor = Self::default();
+        let Some(val) = config
+            .get_preprocessor(NAME)
+            .and_then(|cfg| cfg.get("renderers"))
+        else {
+            return Ok(preprocessor);
+        };
+        let err = || anyhow!("'{NAME}.renderers' expects array of strings");
+        let Value::Array(arr) = val else {
+            return Err(err());
+        };
+        for val in arr {
+            let Value::String(renderer) = val else {
+                return Err(err());
+            };
+            preprocessor.renderers.push(renderer.clone());
+        }
+        Ok(preprocessor)
+    }
+}
+
+/// Conversion options from the `[preprocessor.mathml]` table.
+#[derive(Debug, Default)]
+struct Settings {
+    /// Expand mhchem `\ce{...}` notation before converting.
+    chemistry: bool,
+    /// Delimiters recognised in addition to `$...$` and `$$...$$`.
+    delimiters: Vec<Delimiter>,
+    /// What to do with maths which can't be converted.
+    on_error: OnError,
+    /// Number the display maths, e.g. `(1)`, giving each an `id` to link to.
+    number_equations: bool,
+    /// Whether the numbering restarts in each chapter.
+    number_scope: NumberScope,
+}
+
+/// What to do with maths which can't be converted, set by `on_error`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum OnError {
+    /// Fail the whole book.
+    #[default]
+    Fail,
+    /// Leave the maths as it was written, e.g. `$\left( a$`.
+    /// Numbered maths is still wrapped with its number,
+    /// so the numbering doesn't skip it.
+    Keep,
+    /// Replace the maths with a `<span class="math-error">`,
+    /// holding the maths as it was written in its `data-original` attribute.
+    Placeholder,
+}
+
+/// Where equation numbering restarts, set by `number_scope`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum NumberScope {
+    /// Each chapter is numbered from 1.
+    #[default]
+    Chapter,
+    /// The numbering continues from one chapter to the next.
+    Book,
+}
+
+impl NumberScope {
+    fn new(cfg: &Table) -> Result<Self> {
+        match cfg.get("number_scope") {
+            None => Ok(Self::default()),
+            Some(Value::String(val)) if val == "chapter" => Ok(Self::Chapter),
+            Some(Value::String(val)) if val == "book" => Ok(Self::Book),
+            Some(_) => Err(anyhow!(
+                "'{NAME}.number_scope' expects \"chapter\" or \"book\""
+            )),
+        }
+    }
+}
+
+impl OnError {
+    fn new(cfg: &Table) -> Result<Self> {
+        match cfg.get("on_error") {
+            None => Ok(Self::default()),
+            Some(Value::String(val)) if val == "fail" => Ok(Self::Fail),
+            Some(Value::String(val)) if val == "keep" => Ok(Self::Keep),
+            Some(Value::String(val)) if val == "placeholder" => Ok(Self::Placeholder),
+            Some(_) => Err(anyhow!(
+                "'{NAME}.on_error' expects \"fail\", \"keep\" or \"placeholder\""
+            )),
+        }
+    }
+}
+
+impl Settings {
+    fn new(config: &Config) -> Result<Self> {
+        let Some(cfg) = config.get_preprocessor(NAME) else {
+            return Ok(Self::default());
+        };
+        Ok(Self {
+            chemistry: get_bool(cfg, "chemistry")?,
+            delimiters: get_delimiters(cfg)?,
+            on_error: OnError::new(cfg)?,
+            number_equations: get_bool(cfg, "number_equations")?,
+            number_scope: NumberScope::new(cfg)?,
+        })
+    }
+}
+
+/// Reads an array of tables, each with an `open` and `close` string
+/// and an optional `display` boolean.
+fn get_delimiters(cfg: &Table) -> Result<Vec<Delimiter>> {
+    let Some(val) = cfg.get("delimiters") else {
+        return Ok(Vec::new());
+    };
+    let err =
+        || anyhow!("'{NAME}.delimiters' expects array of tables with 'open' and 'close' strings");
+    let Value::Array(arr) = val else {
+        return Err(err());
+    };
+    let mut delimiters = Vec::new();
+    for val in arr {
+        let Value::Table(tab) = val else {
+            return Err(err());
+        };
+        let (Some(Value::String(open)), Some(Value::String(close))) =
+            (tab.get("open"), tab.get("close"))
+        else {
+            return Err(err());
+        };
+        if open.is_empty() || close.is_empty() {
+            return Err(err());
+        }
+        delimiters.push(Delimiter {
+            open: open.clone(),
+            close: close.clone(),
+            display: get_bool(tab, "display")?,
+        });
+    }
+    Ok(delimiters)
+}
+
+fn get_bool(cfg: &Table, key: &str) -> Result<bool> {
+    match cfg.get(key) {
+        None => Ok(false),
+        Some(Value::Boolean(val)) => Ok(*val),
+        Some(_) => Err(anyhow!("'{NAME}.{key}' expects a boolean")),
+    }
+}
 
 impl Preprocessor for MathMlPreprocessor {
     fn name(&self) -> &str {
-        "replace"
+        NAME
     }
 
-    fn run(&self, _ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
-        let regex_replace = |book_item: &mut BookItem| {
+    fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book> {
+        let settings = Settings::new(&ctx.config)?;
+        // The chapters are converted in parallel, so their contents are
+        // taken out of the book and put back afterwards, in the same order.
+        let mut contents = Vec::new();
+        book.for_each_mut(|book_item| {
+            if let BookItem::Chapter(chapter) = book_item {
+                contents.push(std::mem::take(&mut chapter.content));
+            }
+        });
+        let converted = convert_chapters(&contents, &settings);
+
+        // `for_each_mut` can't stop early, so the first error is kept.
+        let mut result = Ok(());
+        let mut chapters = contents.into_iter().zip(converted);
+        book.for_each_mut(|book_item| {
             let BookItem::Chapter(chapter) = book_item else {
                 return;
             };
-            if let Cow::Owned(new_content) = replace_latex(&chapter.content).unwrap() {
-                chapter.content = new_content
+            // ok to unwrap because there's a content for every chapter
+            let (content, converted) = chapters.next().unwrap();
+            match in_chapter(chapter, converted) {
+                Ok(Some(new_content)) => chapter.content = new_content,
+                Ok(None) => chapter.content = content,
+                Err(err) => {
+                    chapter.content = content;
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                }
             }
-        };
-        book.for_each_mut(regex_replace);
+        });
+        result?;
 
         Ok(book)
     }
 
-    fn supports_renderer(&self, _renderer: &str) -> bool {
-        true
+    fn supports_renderer(&self, renderer: &str) -> bool {
+        renderer == "html" || self.renderers.iter().any(|r| r == renderer)
     }
 }
 
-fn replace_latex(markdown: &str) -> Result<Cow<'_, str>> {
-    let extensions = Options::ENABLE_GFM
+/// Converts the maths in each chapter's content on a thread pool,
+/// returning the new content of each chapter, if it changed, in order.
+fn convert_chapters(contents: &[String], settings: &Settings) -> Vec<Result<Option<String>>> {
+    let first_numbers: Vec<_> =
+        if settings.number_equations && settings.number_scope == NumberScope::Book {
+            // The equations are counted up front,
+            // so each chapter knows where its numbering starts.
+            contents
+                .iter()
+                .scan(1, |next, content| {
+                    let first = *next;
+                    *next += count_equations(content, settings);
+                    Some(first)
+                })
+                .collect()
+        } else {
+            vec![1; contents.len()]
+        };
+    contents
+        .par_iter()
+        .zip(first_numbers)
+        .map(
+            |(content, first_number)| match replace_latex(content, settings, first_number)? {
+                Cow::Owned(new_content) => Ok(Some(new_content)),
+                Cow::Borrowed(_) => Ok(None),
+            },
+        )
+        .collect()
+}
+
+/// Adds the chapter's name and path to an error.
+fn in_chapter<T>(chapter: &Chapter, result: Result<T>) -> Result<T> {
+    result.with_context(|| match &chapter.path {
+        Some(path) => format!(
+            "Failed to process chapter '{}' ({})",
+            chapter.name,
+            path.display()
+        ),
+        None => format!("Failed to process chapter '{}'", chapter.name),
+    })
+}
+
+fn extensions() -> Options {
+    Options::ENABLE_GFM
         | Options::ENABLE_MATH
         | Options::ENABLE_STRIKETHROUGH
-        | Options::ENABLE_TASKLISTS;
+        | Options::ENABLE_TASKLISTS
+}
 
+/// If `number_equations` is set, the display maths is numbered
+/// starting from `first_number`.
+/// Maths which can't be converted still takes a number,
+/// whatever `on_error` is set to.
+fn replace_latex<'a>(
+    markdown: &'a str,
+    settings: &Settings,
+    first_number: usize,
+) -> Result<Cow<'a, str>> {
+    let normalized = normalize_delimiters(markdown, &settings.delimiters, extensions());
+    let markdown = normalized.as_ref();
     let mut replacements = vec![];
-    for (event, range) in Parser::new_ext(markdown, extensions).into_offset_iter() {
-        let style = match event {
-            Event::InlineMath(_) => DisplayStyle::Inline,
-            Event::DisplayMath(_) => DisplayStyle::Block,
-            _ => continue,
+    let mut next_number = first_number;
+    for (range, snippet, style) in find_maths(markdown) {
+        let number =
+            (settings.number_equations && matches!(style, DisplayStyle::Block)).then(|| {
+                next_number += 1;
+                next_number - 1
+            });
+        let snippet = snippet.trim();
+        let converted = if settings.chemistry {
+            expand_chemistry(snippet)
+        } else {
+            Ok(Cow::Borrowed(snippet))
+        }
+        .and_then(|snippet| Ok(escape_mathml_text(&latex_to_mathml(&snippet, style)?)));
+        let mathml = match (converted, settings.on_error) {
+            (Ok(mathml), _) => mathml,
+            (Err(err), OnError::Fail) => return Err(err),
+            (Err(_), OnError::Keep) if number.is_none() => continue,
+            (Err(_), OnError::Keep) => markdown[range.clone()].to_string(),
+            (Err(_), OnError::Placeholder) => {
+                let original = escape_html(&markdown[range.clone()]);
+                format!(r#"<span class="math-error" data-original="{original}">{original}</span>"#)
+            }
+        };
+        let mathml = match number {
+            Some(number) => format!(
+                r##"<span class="equation" id="equation-{number}">{mathml}<a class="equation-number" href="#equation-{number}">({number})</a></span>"##
+            ),
+            None => mathml,
         };
-        let snippet = markdown[range.clone()]
-            .trim_start_matches('$')
-            .trim_end_matches('$');
-        let mathml = latex_to_mathml(snippet, style)?;
         replacements.push((range, mathml));
     }
     if replacements.is_empty() {
-        return Ok(Cow::Borrowed(markdown));
+        return Ok(normalized);
     }
 
     let mut output_md = markdown.to_string();
     for (range, mathml) in replacements.iter().rev() {
         output_md = output_md[..range.start].to_string() + mathml + &output_md[range.end..];
     }
-    return Ok(Cow::Owned(output_md));
+    Ok(Cow::Owned(output_md))
+}
+
+/// The number of display maths which would be numbered in the markdown.
+fn count_equations(markdown: &str, settings: &Settings) -> usize {
+    let normalized = normalize_delimiters(markdown, &settings.delimiters, extensions());
+    find_maths(&normalized)
+        .into_iter()
+        .filter(|(_, _, style)| matches!(style, DisplayStyle::Block))
+        .count()
+}
+
+/// Returns the range, including the delimiters, LaTeX and style of the maths
+/// in the markdown, whose delimiters must already be normalized.
+fn find_maths(markdown: &str) -> Vec<(Range<usize>, CowStr<'_>, DisplayStyle)> {
+    let mut maths = Vec::new();
+    // How many hand-written `<math>` elements the events are inside of.
+    // Dollars inside them, e.g. `<mi>$</mi>`, aren't math delimiters.
+    let mut math_depth = 0usize;
+    // Escaped dollars, e.g. `\$5`, are text rather than math delimiters,
+    // so are left for the renderer.
+    // Raw HTML, e.g. `<div>$5</div>`, is never parsed as math.
+    for (event, range) in Parser::new_ext(markdown, extensions()).into_offset_iter() {
+        // The event holds the LaTeX without its delimiters,
+        // which the parser matches exactly, e.g. `$$$a$$$` is display math
+        // surrounded by stray dollars.
+        let (snippet, style) = match event {
+            Event::Html(html) | Event::InlineHtml(html) => {
+                let (opened, closed) = count_math_tags(&html);
+                math_depth = (math_depth + opened).saturating_sub(closed);
+                continue;
+            }
+            _ if math_depth > 0 => continue,
+            Event::InlineMath(latex) => (latex, DisplayStyle::Inline),
+            Event::DisplayMath(latex) => (latex, DisplayStyle::Block),
+            _ => continue,
+        };
+        // Inline math can't start or end with whitespace, e.g. `$ a $` isn't math,
+        // but display math can, e.g. `$$ b $$`, which is trimmed.
+        // Like inline math, whitespace only display math isn't converted.
+        if !snippet.trim().is_empty() {
+            maths.push((range, snippet, style));
+        }
+    }
+    maths
+}
+
+/// Escapes the characters which can't appear in HTML text or attributes.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            '\'' => escaped += "&#39;",
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes the `<`, `>` and `&` which latex2mathml leaves in the text of elements,
+/// e.g. `<mo><</mo>` for `a < b`, so the MathML is well-formed XML.
+/// Tags and entities, e.g. `&lt;`, are kept as they are.
+fn escape_mathml_text(mathml: &str) -> String {
+    let mut escaped = String::with_capacity(mathml.len());
+    let mut rest = mathml;
+    while let Some(c) = rest.chars().next() {
+        if let Some(len) = tag_len(rest) {
+            escaped += &rest[..len];
+            rest = &rest[len..];
+            continue;
+        }
+        match c {
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '&' if !is_entity(rest) => escaped += "&amp;",
+            c => escaped.push(c),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    escaped
+}
+
+/// The length of the tag at the start of the text, e.g. `<mi>` or `</mi>`,
+/// if there is one.
+fn tag_len(text: &str) -> Option<usize> {
+    let after = text.strip_prefix('<')?;
+    let name = after.strip_prefix('/').unwrap_or(after);
+    let name_len = name
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != ':')
+        .unwrap_or(name.len());
+    if name_len == 0 || !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    if !name[name_len..].starts_with([' ', '>', '/']) {
+        return None;
+    }
+    // A tag can't contain another `<`.
+    let end = after.find(['<', '>'])?;
+    (after.as_bytes()[end] == b'>').then_some(end + 2)
+}
+
+/// Whether the text starts with an entity, e.g. `&amp;` or `&#60;`.
+fn is_entity(text: &str) -> bool {
+    let Some(after) = text.strip_prefix('&') else {
+        return false;
+    };
+    let Some(end) = after.find(';') else {
+        return false;
+    };
+    let name = &after[..end];
+    let name = name.strip_prefix('#').unwrap_or(name);
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Returns the number of `<math>` elements opened and closed in the HTML.
+fn count_math_tags(html: &str) -> (usize, usize) {
+    let html = html.to_ascii_lowercase();
+    let is_tag = |rest: &str| rest.starts_with(|c: char| c == '>' || c.is_ascii_whitespace());
+    let opened = html
+        .match_indices("<math")
+        .filter(|(idx, tag)| is_tag(&html[idx + tag.len()..]))
+        .count();
+    let closed = html.matches("</math>").count();
+    (opened, closed)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    /// Converts the markdown with any numbering starting from 1.
+    fn replace_latex<'a>(markdown: &'a str, settings: &Settings) -> Result<Cow<'a, str>> {
+        super::replace_latex(markdown, settings, 1)
+    }
+
     #[test]
     fn convert_markdown() -> Result<()> {
         let input = r##"
@@ -139,8 +538,380 @@ $$a
 
 <math xmlns="http://www.w3.org/1998/Math/MathML" display="block"><mi>c</mi><mo>=</mo><mi>d</mi></math>a
         "##;
-        let output = replace_latex(input)?;
+        let output = replace_latex(input, &Settings::default())?;
         assert!(expected == output);
         Ok(())
     }
+
+    #[test]
+    fn dollar_edge_cases() -> Result<()> {
+        let settings = Settings::default();
+        let math = |latex, style| latex_to_mathml(latex, style);
+
+        // Escaped dollars never open math.
+        let input = r"It costs \$5, or \$6 with $x$.";
+        let expected = format!(
+            r"It costs \$5, or \$6 with {}.",
+            math("x", DisplayStyle::Inline)?
+        );
+        assert_eq!(replace_latex(input, &settings)?, expected);
+
+        // An escaped dollar inside math is kept.
+        let expected = math(r"\$", DisplayStyle::Block)?;
+        assert_eq!(replace_latex(r"$$\$$$", &settings)?, expected);
+
+        // Adjacent inline maths.
+        let expected = math("a", DisplayStyle::Inline)? + &math("b", DisplayStyle::Inline)?;
+        assert_eq!(replace_latex("$a$$b$", &settings)?, expected);
+
+        // Whitespace only maths isn't maths.
+        assert_eq!(replace_latex("$ $", &settings)?, Cow::Borrowed("$ $"));
+        assert_eq!(replace_latex("$$ $$", &settings)?, Cow::Borrowed("$$ $$"));
+        Ok(())
+    }
+
+    #[test]
+    fn well_formed_xml() -> Result<()> {
+        let settings = Settings::default();
+        for latex in [
+            r"\text{a & b}",
+            r"\text{x < y}",
+            "a < b",
+            "a > b",
+            r"a \& b",
+            r"\text{\&}",
+            r"\mathrm{a<b}",
+        ] {
+            let mathml = replace_latex(&format!("${latex}$"), &settings)?.into_owned();
+            if let Err(err) = roxmltree::Document::parse(&mathml) {
+                panic!("{latex:?} gave {mathml:?}, which isn't XML: {err}");
+            }
+        }
+        let mathml = replace_latex("$a < b \\& c$", &settings)?;
+        assert!(mathml.contains("<mo>&lt;</mo>"), "{mathml}");
+        assert!(mathml.contains(">&amp;</mi>"), "{mathml}");
+        // Entities and tags are left alone.
+        assert_eq!(
+            escape_mathml_text("<mi>&lt;</mi><mo>&#60;</mo><mtext>&</mtext>"),
+            "<mi>&lt;</mi><mo>&#60;</mo><mtext>&amp;</mtext>"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn surrounding_whitespace() -> Result<()> {
+        let settings = Settings::default();
+        let math = |latex, style| latex_to_mathml(latex, style);
+
+        // Inline math can't be padded with whitespace.
+        assert_eq!(replace_latex("$ a $", &settings)?, Cow::Borrowed("$ a $"));
+
+        // Display math can, which is trimmed.
+        let expected = math("b", DisplayStyle::Block)?;
+        assert_eq!(replace_latex("$$ b $$", &settings)?, expected);
+        assert_eq!(replace_latex("$$\n b \n$$", &settings)?, expected);
+
+        // Only the delimiters are removed, leaving any stray dollars.
+        let expected = format!("${}$", math("a", DisplayStyle::Block)?);
+        assert_eq!(replace_latex("$$$a$$$", &settings)?, expected);
+        let expected = format!("${}", math("a", DisplayStyle::Inline)?);
+        assert_eq!(replace_latex("$$a$", &settings)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn chemistry() -> Result<()> {
+        let input = r"$\ce{2H2 + O2 -> 2H2O}$";
+        let expected = r"${2\text{H}_{2} + \text{O}_{2} \rightarrow 2\text{H}_{2}\text{O}}$";
+        let settings = Settings {
+            chemistry: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            replace_latex(input, &settings)?,
+            replace_latex(expected, &Settings::default())?
+        );
+
+        let config: Config = "[preprocessor.mathml]\nchemistry = true".parse()?;
+        assert!(Settings::new(&config)?.chemistry);
+        Ok(())
+    }
+
+    #[test]
+    fn custom_delimiters() -> Result<()> {
+        let config: Config = r#"
+            [[preprocessor.mathml.delimiters]]
+            open = '\('
+            close = '\)'
+            [[preprocessor.mathml.delimiters]]
+            open = '\['
+            close = '\]'
+            display = true
+        "#
+        .parse()?;
+        let settings = Settings::new(&config)?;
+        assert_eq!(
+            replace_latex(r"\(a\) and \[b\]", &settings)?,
+            replace_latex("$a$ and $$b$$", &Settings::default())?
+        );
+
+        let config: Config = "[preprocessor.mathml]\ndelimiters = ['\\(']".parse()?;
+        assert!(Settings::new(&config).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn raw_html() -> Result<()> {
+        let input = r#"$a$ costs
+
+<div class="price">
+  $5 or $6
+</div>
+
+Hand-written <math><mi>$</mi><mo>+</mo><mi>$</mi></math> and $b$.
+
+<math display="block">
+  <mi>$</mi>
+</math>
+"#;
+        let expected = r#"<math xmlns="http://www.w3.org/1998/Math/MathML" display="inline"><mi>a</mi></math> costs
+
+<div class="price">
+  $5 or $6
+</div>
+
+Hand-written <math><mi>$</mi><mo>+</mo><mi>$</mi></math> and <math xmlns="http://www.w3.org/1998/Math/MathML" display="inline"><mi>b</mi></math>.
+
+<math display="block">
+  <mi>$</mi>
+</math>
+"#;
+        assert_eq!(replace_latex(input, &Settings::default())?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn chapter_errors() -> Result<()> {
+        let mut book = Book::new();
+        let content = r"$\left( a$".to_string();
+        book.push_item(Chapter::new("Brackets", content, "brackets.md", Vec::new()));
+        let ctx: PreprocessorContext = serde_json::from_value(serde_json::json!({
+            "root": "/path/to/book",
+            "config": { "book": {} },
+            "renderer": "html",
+            "mdbook_version": "0.4.40"
+        }))?;
+
+        let err = MathMlPreprocessor::default().run(&ctx, book).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to process chapter 'Brackets' (brackets.md)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn on_error() -> Result<()> {
+        let input = r"$a$ and $\left( a < b$";
+        let settings = |mode| -> Result<Settings> {
+            let config: Config = format!("[preprocessor.mathml]\non_error = '{mode}'").parse()?;
+            Settings::new(&config)
+        };
+        let valid = latex_to_mathml("a", DisplayStyle::Inline)?;
+
+        assert!(replace_latex(input, &Settings::default()).is_err());
+        assert!(replace_latex(input, &settings("fail")?).is_err());
+        assert_eq!(
+            replace_latex(input, &settings("keep")?)?,
+            format!(r"{valid} and $\left( a < b$")
+        );
+        assert_eq!(
+            replace_latex(input, &settings("placeholder")?)?,
+            format!(
+                r#"{valid} and <span class="math-error" data-original="$\left( a &lt; b$">$\left( a &lt; b$</span>"#
+            )
+        );
+        assert!(settings("ignore").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn numbered_equations() -> Result<()> {
+        let settings = |scope| -> Result<Settings> {
+            let config: Config =
+                format!("[preprocessor.mathml]\nnumber_equations = true\nnumber_scope = '{scope}'")
+                    .parse()?;
+            Settings::new(&config)
+        };
+        let math = |latex, style| latex_to_mathml(latex, style);
+        let equation = |latex, number| -> Result<String> {
+            Ok(format!(
+                r##"<span class="equation" id="equation-{number}">{}<a class="equation-number" href="#equation-{number}">({number})</a></span>"##,
+                math(latex, DisplayStyle::Block)?
+            ))
+        };
+        let input = "$$a$$\n\n$b$\n\n$$c$$\n";
+        let expected = format!(
+            "{}\n\n{}\n\n{}\n",
+            equation("a", 1)?,
+            math("b", DisplayStyle::Inline)?,
+            equation("c", 2)?
+        );
+        assert_eq!(replace_latex(input, &settings("chapter")?)?, expected);
+        // Without the option, display maths isn't numbered.
+        assert!(!replace_latex(input, &Settings::default())?.contains("equation"));
+
+        // With a book scope, the numbering continues into the next chapter.
+        let contents = [input.to_string(), "$$d$$\n".to_string()];
+        let last_chapter = |settings| -> Result<Option<String>> {
+            convert_chapters(&contents, &settings).pop().unwrap()
+        };
+        assert_eq!(
+            last_chapter(settings("chapter")?)?,
+            Some(format!("{}\n", equation("d", 1)?))
+        );
+        assert_eq!(
+            last_chapter(settings("book")?)?,
+            Some(format!("{}\n", equation("d", 3)?))
+        );
+        assert!(settings("section").is_err());
+
+        // Kept maths is numbered too, so the numbering doesn't skip it.
+        let config: Config =
+            "[preprocessor.mathml]\nnumber_equations = true\non_error = 'keep'".parse()?;
+        let keep = Settings::new(&config)?;
+        let input = "$$a$$\n\n$$\\left( a$$\n\n$$c$$\n";
+        let expected = format!(
+            "{}\n\n{}\n\n{}\n",
+            equation("a", 1)?,
+            r##"<span class="equation" id="equation-2">$$\left( a$$<a class="equation-number" href="#equation-2">(2)</a></span>"##,
+            equation("c", 3)?
+        );
+        assert_eq!(replace_latex(input, &keep)?, expected);
+        assert_eq!(count_equations(input, &keep), 3);
+        Ok(())
+    }
+
+    fn chapter_contents(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|idx| {
+                format!(
+                    "# Chapter {idx}\n\n$x_{idx} = \\frac{{a}}{{b}}$ and\n\n\
+                     $$\\sum_{{i=0}}^{idx} i$$\n\nno maths\n"
+                )
+                .repeat(20)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parallel_conversion() -> Result<()> {
+        let settings = Settings::default();
+        let mut contents = chapter_contents(20);
+        contents.push("no maths\n".to_string());
+        let converted = convert_chapters(&contents, &settings);
+        assert_eq!(converted.len(), contents.len());
+        for (content, converted) in contents.iter().zip(converted) {
+            let expected = match replace_latex(content, &settings)? {
+                Cow::Owned(new_content) => Some(new_content),
+                Cow::Borrowed(_) => None,
+            };
+            assert_eq!(converted?, expected);
+        }
+
+        // The book keeps its structure and order.
+        let mut book = Book::new();
+        let mut parent = Chapter::new("Parent", contents[0].clone(), "parent.md", Vec::new());
+        parent.sub_items.push(BookItem::Chapter(Chapter::new(
+            "Child",
+            contents[1].clone(),
+            "child.md",
+            vec!["Parent".to_string()],
+        )));
+        book.push_item(parent);
+        book.push_item(BookItem::Separator);
+        book.push_item(Chapter::new(
+            "Last",
+            contents[20].clone(),
+            "last.md",
+            Vec::new(),
+        ));
+        let ctx: PreprocessorContext = serde_json::from_value(serde_json::json!({
+            "root": "/path/to/book",
+            "config": { "book": {} },
+            "renderer": "html",
+            "mdbook_version": "0.4.40"
+        }))?;
+        let book = MathMlPreprocessor::default().run(&ctx, book)?;
+        let chapters: Vec<_> = book
+            .iter()
+            .filter_map(|item| match item {
+                BookItem::Chapter(chapter) => {
+                    Some((chapter.name.as_str(), chapter.content.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            chapters,
+            [
+                (
+                    "Parent",
+                    replace_latex(&contents[0], &settings)?.into_owned()
+                ),
+                (
+                    "Child",
+                    replace_latex(&contents[1], &settings)?.into_owned()
+                ),
+                ("Last", contents[20].clone()),
+            ]
+        );
+        Ok(())
+    }
+
+    /// Compares converting the chapters one at a time with the thread pool.
+    /// Run with `cargo test --release -p mdbook-mathml -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_conversion() -> Result<()> {
+        let settings = Settings::default();
+        let contents = chapter_contents(200);
+        let runs = 10;
+
+        let start = std::time::Instant::now();
+        for _ in 0..runs {
+            for content in &contents {
+                replace_latex(content, &settings)?;
+            }
+        }
+        let sequential = start.elapsed() / runs;
+
+        let start = std::time::Instant::now();
+        for _ in 0..runs {
+            convert_chapters(&contents, &settings);
+        }
+        let parallel = start.elapsed() / runs;
+        println!(
+            "{sequential:?} sequentially, {parallel:?} on {} threads per run",
+            rayon::current_num_threads()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn supported_renderers() -> Result<()> {
+        let preprocessor = MathMlPreprocessor::new(&Config::default())?;
+        assert!(preprocessor.supports_renderer("html"));
+        assert!(!preprocessor.supports_renderer("latex"));
+
+        let config: Config = "[preprocessor.mathml]\nrenderers = [\"latex\"]".parse()?;
+        let preprocessor = MathMlPreprocessor::new(&config)?;
+        assert!(preprocessor.supports_renderer("html"));
+        assert!(preprocessor.supports_renderer("latex"));
+        assert!(!preprocessor.supports_renderer("epub"));
+
+        let config: Config = "[preprocessor.mathml]\nrenderers = \"latex\"".parse()?;
+        assert!(MathMlPreprocessor::new(&config).is_err());
+        Ok(())
+    }
 }
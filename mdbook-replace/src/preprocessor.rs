@@ -1,17 +1,90 @@
 use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
 
-use anyhow::{anyhow, Result};
-use mdbook::book::{Book, BookItem};
+use anyhow::{anyhow, Context, Result};
+use mdbook::book::{Book, BookItem, Chapter};
 use mdbook::preprocess::{Preprocessor, PreprocessorContext};
 use toml::value::{Table, Value};
 
-use mdutils::links::replace_links;
+use mdutils::links::{is_local_link, replace_links_with_text};
+use mdutils::text::{get_code_blocks, get_text_spans};
 use regex::Regex;
 use relative_path::PathExt;
-use url::Url;
 
 pub struct RegexReplace;
 
+/// A single entry of a replacements array.
+struct Rule<'a> {
+    regex: Regex,
+    replacement: &'a str,
+    /// The renderers this rule applies to.
+    /// If `None`, the rule applies to all renderers.
+    renderers: Option<Vec<&'a str>>,
+    /// Replace every match rather than only the first.
+    replace_all: bool,
+    /// Only match against the link's path,
+    /// reattaching the query and fragment, e.g. `#section`, afterwards.
+    preserve_fragment: bool,
+    /// Match against the link's text, e.g. `docs` in `[docs](old.md)`,
+    /// rather than its destination.
+    match_text: bool,
+    /// For text replacements, the languages of the code blocks to replace in,
+    /// e.g. `toml`. If `None`, the rule replaces in prose instead.
+    languages: Option<Vec<&'a str>>,
+}
+impl Rule<'_> {
+    fn applies_to(&self, renderer: &str) -> bool {
+        self.renderers
+            .as_ref()
+            .is_none_or(|renderers| renderers.contains(&renderer))
+    }
+
+    /// Returns `Cow::Borrowed` if the regex didn't match.
+    /// A rule matching the link's text replaces the whole destination
+    /// with its replacement, expanded with the captures from the text.
+    fn replace<'h>(&self, link: &'h str, text: Option<&str>) -> Cow<'h, str> {
+        let (path, suffix) = if self.preserve_fragment {
+            link.split_at(link.find(['?', '#']).unwrap_or(link.len()))
+        } else {
+            (link, "")
+        };
+        if self.match_text {
+            let Some(captures) = text.and_then(|text| self.regex.captures(text)) else {
+                return Cow::Borrowed(link);
+            };
+            let mut new_path = String::new();
+            captures.expand(self.replacement, &mut new_path);
+            return Cow::Owned(new_path + suffix);
+        }
+        let new_path = if self.replace_all {
+            self.regex.replace_all(path, self.replacement)
+        } else {
+            self.regex.replace(path, self.replacement)
+        };
+        match new_path {
+            Cow::Owned(new_path) => Cow::Owned(new_path + suffix),
+            Cow::Borrowed(_) => Cow::Borrowed(link),
+        }
+    }
+
+    /// Whether this text replacement applies to a code block in the language.
+    fn applies_to_code(&self, language: Option<&str>) -> bool {
+        self.languages
+            .as_ref()
+            .is_some_and(|languages| language.is_some_and(|lang| languages.contains(&lang)))
+    }
+
+    /// Returns `Cow::Borrowed` if the regex didn't match.
+    fn replace_text<'h>(&self, text: &'h str) -> Cow<'h, str> {
+        if self.replace_all {
+            self.regex.replace_all(text, self.replacement)
+        } else {
+            self.regex.replace(text, self.replacement)
+        }
+    }
+}
+
 impl RegexReplace {
     pub fn new() -> RegexReplace {
         RegexReplace
@@ -21,7 +94,7 @@ impl RegexReplace {
         &self,
         preproc_cfg: &'a Table,
         rep_type: &str,
-    ) -> Result<Vec<(Regex, &'a str)>> {
+    ) -> Result<Vec<Rule<'a>>> {
         let mut replacements = Vec::new();
         let Some(val) = preproc_cfg.get(rep_type) else {
             return Ok(replacements);
@@ -37,7 +110,7 @@ impl RegexReplace {
         let Value::Array(arr) = val else {
             return err_msg();
         };
-        for val in arr {
+        for (idx, val) in arr.iter().enumerate() {
             let Value::Table(tab) = val else {
                 return err_msg();
             };
@@ -46,12 +119,91 @@ impl RegexReplace {
             else {
                 return err_msg();
             };
-            replacements.push((Regex::new(pattern)?, replacement))
+            let strings_err = |key| {
+                anyhow!(
+                    "'{}.{rep_type}.{key}' expects an array of strings",
+                    self.name()
+                )
+            };
+            let get_strings = |key| match tab.get(key) {
+                None => Ok(None),
+                Some(Value::Array(arr)) => {
+                    let mut strings = Vec::new();
+                    for val in arr {
+                        let Value::String(string) = val else {
+                            return Err(strings_err(key));
+                        };
+                        strings.push(string.as_str());
+                    }
+                    Ok(Some(strings))
+                }
+                Some(_) => Err(strings_err(key)),
+            };
+            let renderers = get_strings("renderers")?;
+            let languages = get_strings("languages")?;
+            let get_bool = |key| match tab.get(key) {
+                None => Ok(false),
+                Some(Value::Boolean(val)) => Ok(*val),
+                Some(_) => Err(anyhow!(
+                    "'{}.{rep_type}.{key}' expects a boolean",
+                    self.name()
+                )),
+            };
+            let match_text = match tab.get("match") {
+                None => false,
+                Some(Value::String(target)) if target == "dest" => false,
+                Some(Value::String(target)) if target == "text" => true,
+                Some(_) => {
+                    return Err(anyhow!(
+                        "'{}.{rep_type}.match' expects \"text\" or \"dest\"",
+                        self.name()
+                    ))
+                }
+            };
+            // An anchored pattern must match the whole link.
+            let regex = if get_bool("anchored")? {
+                Regex::new(&format!("^(?:{pattern})$"))
+            } else {
+                Regex::new(pattern)
+            }
+            .with_context(|| {
+                format!(
+                    "Invalid regex '{pattern}' in '{}.{rep_type}[{idx}]'",
+                    self.name()
+                )
+            })?;
+            replacements.push(Rule {
+                regex,
+                replacement,
+                renderers,
+                replace_all: get_bool("replace_all")?,
+                preserve_fragment: get_bool("preserve_fragment")?,
+                match_text,
+                languages,
+            })
         }
         Ok(replacements)
     }
 }
 
+impl RegexReplace {
+    /// Reads the file named by the `rules_file` key, relative to the book's root,
+    /// which can contain `link_replacements`, `local_link_replacements`
+    /// and `text_replacements` arrays, like the preprocessor's table in `book.toml`.
+    fn read_rules_file(&self, preproc_cfg: &Table, root: &Path) -> Result<Option<Table>> {
+        let path = match preproc_cfg.get("rules_file") {
+            None => return Ok(None),
+            Some(Value::String(path)) => root.join(path),
+            Some(_) => return Err(anyhow!("'{}.rules_file' expects a string", self.name())),
+        };
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Couldn't read rules file {}", path.display()))?;
+        let rules = toml::from_str(&content)
+            .with_context(|| format!("Invalid rules file {}", path.display()))?;
+        Ok(Some(rules))
+    }
+}
+
 impl Preprocessor for RegexReplace {
     fn name(&self) -> &str {
         "replace"
@@ -61,56 +213,99 @@ impl Preprocessor for RegexReplace {
         let Some(preproc_cfg) = ctx.config.get_preprocessor(self.name()) else {
             return Ok(book);
         };
-        let link_replacements = self.get_replacements(preproc_cfg, "link_replacements")?;
-        let local_link_replacements =
+        let rules_file = self.read_rules_file(preproc_cfg, &ctx.root)?;
+        let mut link_replacements = self.get_replacements(preproc_cfg, "link_replacements")?;
+        let mut local_link_replacements =
             self.get_replacements(preproc_cfg, "local_link_replacements")?;
+        let mut text_replacements = self.get_replacements(preproc_cfg, "text_replacements")?;
+        // The rules in `book.toml` come before, so take precedence over, those in the file.
+        if let Some(rules) = &rules_file {
+            link_replacements.extend(self.get_replacements(rules, "link_replacements")?);
+            local_link_replacements
+                .extend(self.get_replacements(rules, "local_link_replacements")?);
+            text_replacements.extend(self.get_replacements(rules, "text_replacements")?);
+        }
+        link_replacements.retain(|rule| rule.applies_to(&ctx.renderer));
+        local_link_replacements.retain(|rule| rule.applies_to(&ctx.renderer));
+        text_replacements.retain(|rule| rule.applies_to(&ctx.renderer));
+        let has_link_rules = !link_replacements.is_empty() || !local_link_replacements.is_empty();
+        // Skip parsing every chapter if there's nothing to replace.
+        if !has_link_rules && text_replacements.is_empty() {
+            return Ok(book);
+        }
 
+        // `for_each_mut` can't stop early, so the first error is kept
+        // and the remaining chapters are skipped.
+        let mut result = Ok(());
         let regex_replace = |book_item: &mut BookItem| {
             let BookItem::Chapter(chapter) = book_item else {
                 return;
             };
-            let chapter_path_opt = chapter.path.as_ref().map(|chapter_file| {
-                let mut path = ctx.root.clone();
-                path.push(chapter_file);
-                path.pop();
-                path
-            });
-            let replace_fn = |link: &str| {
+            if result.is_err() {
+                return;
+            }
+            // Local links are only resolved if there are rules to match them against.
+            let chapter_path_opt = match &chapter.path {
+                Some(chapter_file) if !local_link_replacements.is_empty() => {
+                    let mut path = ctx.root.join(chapter_file);
+                    path.pop();
+                    Some(path)
+                }
+                _ => None,
+            };
+            let replace_fn = |link: &str, text: Option<&str>| {
                 // If it's a local link, run through the local link replacements.
-                let is_not_url = Url::parse(link).is_err();
-                if let (Some(chapter_path), true) = (&chapter_path_opt, is_not_url) {
-                    let absolute_path = {
-                        let mut path = chapter_path.clone();
-                        path.push(link);
-                        path
-                    };
+                let is_local = is_local_link(link);
+                if let (Some(chapter_path), true) = (&chapter_path_opt, is_local) {
+                    let absolute_path = chapter_path.join(link);
                     let relative_path = absolute_path.relative_to(&ctx.root)?.normalize();
 
-                    for (re, replacement) in &local_link_replacements {
-                        if let Cow::Owned(new_link) =
-                            re.replace(relative_path.as_str(), *replacement)
-                        {
+                    for rule in &local_link_replacements {
+                        if let Cow::Owned(new_link) = rule.replace(relative_path.as_str(), text) {
                             return Ok(Some(new_link));
                         }
                     }
                 }
                 // If no local link replacements have matched,
                 // run through the link replacements.
-                for (re, replacement) in &link_replacements {
-                    if let Cow::Owned(new_link) = re.replace(link, *replacement) {
+                for rule in &link_replacements {
+                    if let Cow::Owned(new_link) = rule.replace(link, text) {
                         return Ok(Some(new_link));
                     }
                 }
                 Ok(None)
             };
 
-            let content = &chapter.content;
-            // It's safe to unwrap here, because we know `replace_fn` always returns Ok.
-            if let Cow::Owned(new_content) = replace_links(content, replace_fn).unwrap() {
-                chapter.content = new_content
+            let new_content = if has_link_rules {
+                replace_links_with_text(&chapter.content, replace_fn)
+                    .map(|(new_content, skipped)| {
+                        for range in skipped {
+                            eprintln!(
+                                "warning: skipped the link '{}' in chapter '{}', \
+                                 as it's nested inside another link",
+                                &chapter.content[range], chapter.name
+                            );
+                        }
+                        new_content
+                    })
+                    .map_err(Into::into)
+            } else {
+                Ok(Cow::Borrowed(chapter.content.as_str()))
+            };
+            match in_chapter(chapter, new_content) {
+                Ok(Cow::Owned(new_content)) => chapter.content = new_content,
+                Ok(Cow::Borrowed(_)) => {}
+                Err(err) => result = Err(err),
+            }
+            if !text_replacements.is_empty() {
+                if let Cow::Owned(new_content) = replace_text(&chapter.content, &text_replacements)
+                {
+                    chapter.content = new_content;
+                }
             }
         };
         book.for_each_mut(regex_replace);
+        result?;
 
         Ok(book)
     }
@@ -120,9 +315,124 @@ impl Preprocessor for RegexReplace {
     }
 }
 
+/// Applies every text replacement, in order, to each span of prose,
+/// or to the code blocks in the rule's languages.
+/// Link destinations and other markup are never replaced in.
+fn replace_text<'a>(content: &'a str, rules: &[Rule]) -> Cow<'a, str> {
+    let mut spans: Vec<_> = get_text_spans(content)
+        .into_iter()
+        .map(|span| (span, None))
+        .collect();
+    let blocks = get_code_blocks(content);
+    for block in &blocks {
+        let language = block.language.as_deref();
+        if rules.iter().any(|rule| rule.applies_to_code(language)) {
+            spans.extend(
+                block
+                    .spans
+                    .iter()
+                    .map(|span| (span.clone(), Some(language))),
+            );
+        }
+    }
+    spans.sort_by_key(|(span, _)| span.start);
+
+    let mut new_content = String::new();
+    let mut cursor = 0;
+    for (span, code) in spans {
+        let mut text = Cow::Borrowed(&content[span.clone()]);
+        for rule in rules {
+            let applies = match code {
+                Some(language) => rule.applies_to_code(language),
+                None => rule.languages.is_none(),
+            };
+            if applies {
+                if let Cow::Owned(new_text) = rule.replace_text(&text) {
+                    text = Cow::Owned(new_text);
+                }
+            }
+        }
+        if let Cow::Owned(text) = text {
+            new_content += &content[cursor..span.start];
+            new_content += &text;
+            cursor = span.end;
+        }
+    }
+    if cursor == 0 {
+        return Cow::Borrowed(content);
+    }
+    new_content += &content[cursor..];
+    Cow::Owned(new_content)
+}
+
+/// Adds the chapter's name and path to an error.
+fn in_chapter<T>(chapter: &Chapter, result: Result<T>) -> Result<T> {
+    result.with_context(|| match &chapter.path {
+        Some(path) => format!(
+            "Failed to process chapter '{}' ({})",
+            chapter.name,
+            path.display()
+        ),
+        None => format!("Failed to process chapter '{}'", chapter.name),
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use serde_json::json;
+
+    /// Runs the preprocessor over a single chapter, returning its new content.
+    fn run_chapter(
+        renderer: &str,
+        preproc_cfg: serde_json::Value,
+        content: &str,
+    ) -> Result<String> {
+        run_chapter_in(Path::new("/path/to/book"), renderer, preproc_cfg, content)
+    }
+
+    /// Like [`run_chapter`], but for a book at the given root.
+    fn run_chapter_in(
+        root: &Path,
+        renderer: &str,
+        preproc_cfg: serde_json::Value,
+        content: &str,
+    ) -> Result<String> {
+        let input = json!([
+            {
+                "root": root,
+                "config": {
+                    "book": { "src": "src" },
+                    "preprocessor": { "replace": preproc_cfg }
+                },
+                "renderer": renderer,
+                "mdbook_version": "0.4.21"
+            },
+            {
+                "sections": [
+                    {
+                        "Chapter": {
+                            "name": "Chapter 1",
+                            "content": content,
+                            "number": [1],
+                            "sub_items": [],
+                            "path": "chapter_1.md",
+                            "source_path": "chapter_1.md",
+                            "parent_names": []
+                        }
+                    }
+                ],
+                "__non_exhaustive": null
+            }
+        ]);
+        let (ctx, book) =
+            mdbook::preprocess::CmdPreprocessor::parse_input(input.to_string().as_bytes())?;
+        let book = RegexReplace::new().run(&ctx, book)?;
+        match book.sections.into_iter().next() {
+            Some(BookItem::Chapter(chapter)) => Ok(chapter.content),
+            _ => Err(anyhow!("missing chapter")),
+        }
+    }
 
     #[test]
     fn preprocessor_run() -> Result<()> {
@@ -181,4 +491,305 @@ mod test {
         assert_eq!(actual, expected);
         Ok(())
     }
+
+    /// Times the preprocessor over a synthetic 200 chapter book.
+    /// Run with `cargo test --release -p mdbook-replace -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_run() -> Result<()> {
+        let content = "# Chapter\n\nSee [the intro](../intro.md#start), [a note](notes/a.md) \
+                       and <https://hugom.uk>.\n\n[ref]: ./ref.md\n"
+            .repeat(20);
+        let chapters: Vec<_> = (0..200)
+            .map(|idx| {
+                json!({
+                    "Chapter": {
+                        "name": format!("Chapter {idx}"),
+                        "content": content,
+                        "number": [idx],
+                        "sub_items": [],
+                        "path": format!("part/chapter_{idx}.md"),
+                        "source_path": format!("part/chapter_{idx}.md"),
+                        "parent_names": []
+                    }
+                })
+            })
+            .collect();
+        let input = json!([
+            {
+                "root": "/path/to/book",
+                "config": {
+                    "book": { "src": "src" },
+                    "preprocessor": {
+                        "replace": {
+                            "link_replacements": [
+                                { "regex": "^https://hugom.uk", "replacement": "https://hugom.uk/x" }
+                            ],
+                            "local_link_replacements": [
+                                { "regex": "^notes/(.*)", "replacement": "/notes/$1" }
+                            ]
+                        }
+                    }
+                },
+                "renderer": "html",
+                "mdbook_version": "0.4.21"
+            },
+            { "sections": chapters, "__non_exhaustive": null }
+        ]);
+        let (ctx, book) =
+            mdbook::preprocess::CmdPreprocessor::parse_input(input.to_string().as_bytes())?;
+
+        let runs = 10;
+        let start = std::time::Instant::now();
+        for _ in 0..runs {
+            RegexReplace::new().run(&ctx, book.clone())?;
+        }
+        println!("{:?} per run", start.elapsed() / runs);
+        Ok(())
+    }
+
+    #[test]
+    fn chapter_errors() {
+        let chapter = Chapter::new("Intro", String::new(), "guide/intro.md", Vec::new());
+        let err = in_chapter::<()>(&chapter, Err(anyhow!("bad link"))).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "Failed to process chapter 'Intro' (guide/intro.md): bad link"
+        );
+
+        let draft = Chapter::new_draft("Draft", Vec::new());
+        let err = in_chapter::<()>(&draft, Err(anyhow!("bad link"))).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "Failed to process chapter 'Draft': bad link"
+        );
+    }
+
+    #[test]
+    fn anchored_and_replace_all() -> Result<()> {
+        let cfg = json!({
+            "link_replacements": [
+                { "regex": "a.md", "replacement": "b.md", "anchored": true }
+            ]
+        });
+        assert_eq!(
+            run_chapter("html", cfg.clone(), "[a](aa.md)\n")?,
+            "[a](aa.md)\n"
+        );
+        assert_eq!(run_chapter("html", cfg, "[a](a.md)\n")?, "[a](b.md)\n");
+
+        let input = "[a](a-b-c.md)\n";
+        let cfg = json!({ "link_replacements": [{ "regex": "-", "replacement": "_" }] });
+        assert_eq!(run_chapter("html", cfg, input)?, "[a](a_b-c.md)\n");
+        let cfg = json!({
+            "link_replacements": [{ "regex": "-", "replacement": "_", "replace_all": true }]
+        });
+        assert_eq!(run_chapter("html", cfg, input)?, "[a](a_b_c.md)\n");
+
+        let cfg = json!({
+            "link_replacements": [{ "regex": "-", "replacement": "_", "replace_all": "yes" }]
+        });
+        assert!(run_chapter("html", cfg, input).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_regex() {
+        let cfg = json!({
+            "link_replacements": [{ "regex": "^a", "replacement": "b" }],
+            "local_link_replacements": [
+                { "regex": "^a", "replacement": "b" },
+                { "regex": "(unclosed", "replacement": "b" }
+            ]
+        });
+        let err = run_chapter("html", cfg, "[a](a.md)\n").unwrap_err();
+        assert!(format!("{err:#}")
+            .starts_with("Invalid regex '(unclosed' in 'replace.local_link_replacements[1]': "));
+    }
+
+    #[test]
+    fn preserve_fragment() -> Result<()> {
+        let cfg = json!({
+            "link_replacements": [
+                {
+                    "regex": "old.md",
+                    "replacement": "new.md",
+                    "anchored": true,
+                    "preserve_fragment": true
+                }
+            ]
+        });
+        let input = "[a](old.md#sec) [b](old.md?x=1#sec) [c](old.md) [d](bold.md#sec)\n";
+        assert_eq!(
+            run_chapter("html", cfg, input)?,
+            "[a](new.md#sec) [b](new.md?x=1#sec) [c](new.md) [d](bold.md#sec)\n"
+        );
+
+        // Without it, the anchored pattern has to match the fragment too.
+        let cfg = json!({
+            "link_replacements": [{ "regex": "old.md", "replacement": "new.md", "anchored": true }]
+        });
+        assert_eq!(
+            run_chapter("html", cfg, "[a](old.md#sec)\n")?,
+            "[a](old.md#sec)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn match_text() -> Result<()> {
+        let cfg = json!({
+            "link_replacements": [
+                {
+                    "regex": "docs",
+                    "replacement": "https://docs.hugom.uk/",
+                    "match": "text",
+                    "anchored": true,
+                    "preserve_fragment": true
+                },
+                { "regex": "^issue #([0-9]+)$", "replacement": "https://git.hugom.uk/$1", "match": "text" },
+                { "regex": "docs", "replacement": "dest.md", "match": "dest" }
+            ]
+        });
+        let input = "[docs](old.md#intro) [docs](https://old.hugom.uk) [the docs](x.md) \
+                     [issue #12](x.md) [a](docs)\n";
+        assert_eq!(
+            run_chapter("html", cfg, input)?,
+            "[docs](https://docs.hugom.uk/#intro) [docs](https://docs.hugom.uk/) [the docs](x.md) \
+             [issue #12](https://git.hugom.uk/12) [a](dest.md)\n"
+        );
+
+        let cfg = json!({
+            "link_replacements": [{ "regex": "a", "replacement": "b", "match": "title" }]
+        });
+        assert!(run_chapter("html", cfg, "[a](a.md)\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rules_file() -> Result<()> {
+        let tmp = tempfile::TempDir::new()?;
+        fs::write(
+            tmp.path().join("link-rules.toml"),
+            r#"
+                [[link_replacements]]
+                regex = "^a"
+                replacement = "file"
+
+                [[link_replacements]]
+                regex = "^b"
+                replacement = "file"
+            "#,
+        )?;
+        let cfg = json!({
+            "rules_file": "link-rules.toml",
+            "link_replacements": [{ "regex": "^a", "replacement": "inline" }]
+        });
+        assert_eq!(
+            run_chapter_in(tmp.path(), "html", cfg, "[a](a.md) [b](b.md)\n")?,
+            "[a](inline.md) [b](file.md)\n"
+        );
+
+        let cfg = json!({ "rules_file": "missing.toml" });
+        let err = run_chapter_in(tmp.path(), "html", cfg, "[a](a.md)\n").unwrap_err();
+        assert!(err.to_string().starts_with("Couldn't read rules file"));
+        Ok(())
+    }
+
+    #[test]
+    fn protocol_relative_links() -> Result<()> {
+        let cfg = json!({
+            "link_replacements": [
+                { "regex": "^//cdn/", "replacement": "https://cdn.hugom.uk/" }
+            ],
+            "local_link_replacements": [
+                { "regex": "img\\.png$", "replacement": "local.png" }
+            ]
+        });
+        // Protocol relative links are external, so only the link rules apply.
+        assert_eq!(
+            run_chapter("html", cfg, "![a](//cdn/img.png) ![b](img.png)\n")?,
+            "![a](https://cdn.hugom.uk/img.png) ![b](local.png)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nested_links() -> Result<()> {
+        let cfg = json!({
+            "link_replacements": [{ "regex": "^(.*)\\.md$", "replacement": "$1.html" }]
+        });
+        // Links in the text of other links are rewritten separately.
+        let input = "\
+[<a href=\"inner.md\">inner</a>](outer.md)
+[<https://hugom.uk/a.md>](outer.md)
+[[inner](inner.md)](outer.md)
+";
+        assert_eq!(
+            run_chapter("html", cfg, input)?,
+            "\
+[<a href=\"inner.html\">inner</a>](outer.html)
+[<https://hugom.uk/a.html>](outer.html)
+[[inner](inner.html)](outer.md)
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn text_replacements() -> Result<()> {
+        let cfg = json!({
+            "text_replacements": [
+                { "regex": "0\\.1", "replacement": "0.2", "replace_all": true, "languages": ["toml"] },
+                { "regex": "Version", "replacement": "Release" }
+            ]
+        });
+        let input = "\
+Version 0.1 of [Version](v0.1.md).
+
+```toml
+version = \"0.1\"
+other = \"0.1\"
+```
+
+```rust
+let version = \"0.1\"; // Version
+```
+";
+        assert_eq!(
+            run_chapter("html", cfg, input)?,
+            "\
+Release 0.1 of [Release](v0.1.md).
+
+```toml
+version = \"0.2\"
+other = \"0.2\"
+```
+
+```rust
+let version = \"0.1\"; // Version
+```
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn renderer_rules() -> Result<()> {
+        let cfg = json!({
+            "link_replacements": [
+                { "regex": "^bar", "replacement": "html", "renderers": ["html"] },
+                { "regex": "^bar", "replacement": "any" }
+            ]
+        });
+        let input = "[foo](bar.md)\n";
+        assert_eq!(run_chapter("html", cfg.clone(), input)?, "[foo](html.md)\n");
+        assert_eq!(run_chapter("epub", cfg, input)?, "[foo](any.md)\n");
+
+        let cfg = json!({
+            "link_replacements": [{ "regex": "^bar", "replacement": "html", "renderers": "html" }]
+        });
+        assert!(run_chapter("html", cfg, input).is_err());
+        Ok(())
+    }
 }
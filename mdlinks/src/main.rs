@@ -0,0 +1,63 @@
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use regex::Regex;
+
+use mdutils::links::{canonicalize_links, replace_links};
+
+/// Reads markdown from stdin, rewrites its links and writes it to stdout.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// A replacement of the form 'regex=>replacement'.
+    /// Each link is rewritten by the first replacement whose regex matches it.
+    /// The replacement can refer to capture groups, e.g. `$1`.
+    #[arg(short, long = "replace", value_parser = parse_rule)]
+    replacements: Vec<Rule>,
+    /// Rewrite every link into the inline form, e.g. `[text](url)`,
+    /// before any replacements.
+    /// Reference links are expanded and the definitions they used removed.
+    #[arg(short, long)]
+    canonicalize: bool,
+}
+
+#[derive(Clone)]
+struct Rule {
+    regex: Regex,
+    replacement: String,
+}
+
+fn parse_rule(rule: &str) -> Result<Rule> {
+    let Some((pattern, replacement)) = rule.split_once("=>") else {
+        return Err(anyhow!("expected 'regex=>replacement'"));
+    };
+    Ok(Rule {
+        regex: Regex::new(pattern)?,
+        replacement: replacement.to_string(),
+    })
+}
+
+fn main() -> Result<()> {
+    let Cli {
+        replacements,
+        canonicalize,
+    } = Cli::parse();
+    let mut content = String::new();
+    io::stdin().read_to_string(&mut content)?;
+    if canonicalize {
+        content = canonicalize_links(&content).into_owned();
+    }
+
+    let new_content = replace_links(&content, |link| {
+        for rule in &replacements {
+            if let Cow::Owned(new_link) = rule.regex.replace(link, &rule.replacement) {
+                return Ok(Some(new_link));
+            }
+        }
+        Ok(None)
+    })?;
+    io::stdout().write_all(new_content.as_bytes())?;
+    Ok(())
+}
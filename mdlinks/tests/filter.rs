@@ -0,0 +1,64 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn pipe_through() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mdlinks"))
+        .args([
+            "--replace",
+            r"^old/(.*)=>new/$1",
+            "-r",
+            "^https://=>http://",
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"[a](old/a.md) [b](b.md) <https://hugom.uk>\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[a](new/a.md) [b](b.md) <http://hugom.uk>\n"
+    );
+}
+
+#[test]
+fn invalid_replacement() {
+    let status = Command::new(env!("CARGO_BIN_EXE_mdlinks"))
+        .args(["--replace", "no arrow"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn canonicalize() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mdlinks"))
+        .args(["--canonicalize", "-r", "^old/=>new/"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"See [a].\n\n[a]: old/a.md\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "See [a](new/a.md).\n"
+    );
+}
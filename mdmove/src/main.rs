@@ -1,103 +1,634 @@
-use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs::{self, ReadDir};
-use std::path::{
-    Component::{self, Normal, RootDir},
-    Path, PathBuf,
-};
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 use anyhow::{anyhow, Result};
 use clap::Parser;
-use pathdiff::diff_paths;
 
-use mdutils::links::replace_links;
-
-#[derive(Debug, Default)]
-struct MoveList(HashMap<PathBuf, PathBuf>);
-impl MoveList {
-    /// Expects the given path to be absolute.
-    fn get_path_after_move(&self, path: &Path) -> Option<PathBuf> {
-        for (from, to) in &self.0 {
-            if path.starts_with(from) {
-                // unwrap safe due to `starts_with` check above
-                let mut new_path = to.join(path.strip_prefix(from).unwrap());
-                new_path = normalize_path(&new_path);
-                return Some(new_path);
-            }
-        }
-        None
-    }
-}
-impl FromIterator<(PathBuf, PathBuf)> for MoveList {
-    fn from_iter<T: IntoIterator<Item = (PathBuf, PathBuf)>>(iter: T) -> MoveList {
-        let mut list = Self::default();
-        list.0.extend(iter);
-        list
-    }
-}
+use mdutils::moves::{plan_moves, LinkStyle, MoveList, MovePlan, RewriteContext, ScanOptions};
+use mdutils::paths::{is_markdown, normalize_path, MARKDOWN_EXTENSIONS};
 
 type ChangeList = HashMap<PathBuf, String>;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
-    /// The paths to be moved
-    #[arg(num_args=2..)]
+    /// The paths to be moved.
+    /// Sources which don't exist but contain glob metacharacters,
+    /// e.g. `drafts/*.md`, are expanded.
+    #[arg(num_args=2.., required_unless_present = "from_file")]
     paths: Vec<PathBuf>,
+    /// Read the moves from a file instead of the command line.
+    /// Each line should contain a source and destination separated by a tab.
+    #[arg(short, long, conflicts_with = "paths")]
+    from_file: Option<PathBuf>,
     /// The root of the notes.
     /// Defaults to the current directory.
     #[arg(short, long)]
     root: Option<PathBuf>,
-    /// Print changes but don't actually perform moves
+    /// The directory relative links are resolved against.
+    /// Defaults to the directory of the file containing the link.
     #[arg(short, long)]
+    link_base: Option<PathBuf>,
+    /// The form of rewritten links, either 'relative' or 'root-absolute'.
+    /// With 'relative', links keep their original form.
+    /// With 'root-absolute', all rewritten links become relative to the root.
+    #[arg(short = 's', long, default_value = "relative")]
+    link_style: LinkStyle,
+    /// The root after the moves, when they relocate the notes under a new root.
+    /// Absolute links, e.g. `/notes/foo.md`, are resolved against the root
+    /// but rewritten relative to the new root.
+    #[arg(long)]
+    new_root: Option<PathBuf>,
+    /// Print changes but don't actually perform moves.
+    /// Implies `--verify`.
+    #[arg(short, long, conflicts_with = "interactive")]
     dry_run: bool,
+    /// Print the changes and ask for confirmation before making them.
+    /// Implies `--verify`.
+    #[arg(short, long)]
+    interactive: bool,
+    /// Warn about any rewritten link that doesn't lead to its target
+    #[arg(long)]
+    verify: bool,
+    /// Move files with `git mv` and stage the rewritten files with `git add`.
+    /// Falls back to plain filesystem operations, with a warning,
+    /// for untracked files, files with uncommitted changes or if git fails.
+    #[arg(short, long)]
+    git: bool,
+    /// Create the destination directory, and any missing parents,
+    /// if it doesn't exist.
+    #[arg(short, long)]
+    make_dirs: bool,
+    /// Overwrite any file already at a destination.
+    #[arg(long)]
+    force: bool,
+    /// The extensions of the markdown files whose links are rewritten.
+    /// Can be given more than once or as a comma separated list.
+    #[arg(
+        short,
+        long = "ext",
+        value_delimiter = ',',
+        default_values_t = MARKDOWN_EXTENSIONS.map(String::from),
+    )]
+    extensions: Vec<String>,
+    /// Also rewrite the paths of moved files in the non-markdown files
+    /// matching this glob, e.g. a `links.toml` mapping slugs to paths.
+    /// Paths relative to the root and absolute paths are replaced as plain text.
+    /// Can be given more than once.
+    #[arg(long = "sidecar")]
+    sidecars: Vec<String>,
+    /// Only rewrite the links in the files under this directory,
+    /// relative to the root, e.g. `docs`.
+    /// Links are still resolved against the whole root,
+    /// and the moved files' own links are always rewritten.
+    /// Can be given more than once.
+    /// Defaults to the whole root.
+    #[arg(long = "scan")]
+    scan_dirs: Vec<PathBuf>,
+    /// Follow symlinks when looking for links to rewrite (the default),
+    /// which rewrites the files they point to, even outside the root.
+    #[arg(long, overrides_with = "no_follow_symlinks")]
+    follow_symlinks: bool,
+    /// Skip symlinks when looking for links to rewrite.
+    #[arg(long, overrides_with = "follow_symlinks")]
+    no_follow_symlinks: bool,
+    /// Exit with an error if any link warning was emitted,
+    /// e.g. about a link to a file which doesn't exist,
+    /// after still making the changes.
+    #[arg(long)]
+    strict: bool,
+    /// Don't print warnings
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Also print every file written
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+/// How much is printed.
+/// Warnings are printed to stderr, everything else to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    /// Nothing but the dry run plan.
+    Quiet,
+    /// The moves and warnings.
+    Normal,
+    /// The moves, warnings and every file written.
+    Verbose,
 }
 
 fn main() -> Result<()> {
+    run(Cli::parse())
+}
+
+fn run(cli: Cli) -> Result<()> {
     let Cli {
         mut paths,
+        from_file,
         root,
+        link_base,
+        link_style,
+        new_root,
         dry_run,
-    } = Cli::parse();
-    let mut destination = paths.pop().unwrap();
-    if destination.is_relative() {
-        destination = normalize_path(&env::current_dir()?.join(destination));
-    }
-    let sources = paths;
+        interactive,
+        verify,
+        git,
+        make_dirs,
+        force,
+        extensions,
+        sidecars,
+        scan_dirs,
+        follow_symlinks: _,
+        no_follow_symlinks,
+        strict,
+        quiet,
+        verbose,
+    } = cli;
+    let extensions: Vec<_> = extensions
+        .iter()
+        .map(|ext| ext.trim_start_matches('.'))
+        .collect();
+    let verbosity = match (quiet, verbose) {
+        (true, _) => Verbosity::Quiet,
+        (_, true) => Verbosity::Verbose,
+        _ => Verbosity::Normal,
+    };
     let root = root
         .map(|r| r.canonicalize())
         .unwrap_or_else(env::current_dir)?;
+    let link_base = link_base.map(|b| b.canonicalize()).transpose()?;
+    let new_root = new_root
+        .map(|r| absolute_path(r).and_then(canonicalize_destination))
+        .transpose()?;
 
-    for source in &sources {
-        if !source.exists() {
-            return Err(anyhow!("{source:?} doesn't exist"));
+    let mut moves = if let Some(move_file) = from_file {
+        read_move_file(&move_file)?
+    } else {
+        let destination = absolute_path(paths.pop().unwrap())?;
+        let sources = expand_sources(paths)?;
+        for source in &sources {
+            check_source(source)?;
         }
-        if source.to_str().is_none() {
-            return Err(anyhow!("{source:?} isn't valid utf8"));
+        get_move_list(sources, destination, make_dirs)?
+    };
+    for source in skip_self_moves(&mut moves) {
+        if verbosity >= Verbosity::Normal {
+            println!("skipping {source:#?}, which is already at its destination");
+        }
+    }
+    if moves.0.is_empty() {
+        return Ok(());
+    }
+    if !force {
+        check_overwrites(&moves)?;
+    }
+    let ctx = RewriteContext {
+        moves: &moves,
+        root: &root,
+        link_base: link_base.as_deref(),
+        new_root: new_root.as_deref(),
+        link_style,
+        verify: verify || dry_run || interactive,
+    };
+    let scan_dirs = scan_dirs
+        .iter()
+        .map(|dir| root.join(dir).canonicalize())
+        .collect::<io::Result<Vec<_>>>()?;
+    let scan_opts = ScanOptions {
+        extensions: &extensions,
+        follow_symlinks: !no_follow_symlinks,
+        scan_dirs: &scan_dirs,
+    };
+    let mut warnings = orphaned_index_warnings(&moves, &extensions)?;
+    let MovePlan {
+        rewrites: mut changes,
+        warnings: plan_warnings,
+        ..
+    } = plan_moves(&ctx, &scan_opts)?;
+    // Only the warnings from rewriting links count towards --strict.
+    let link_warnings = plan_warnings.len();
+    warnings.extend(plan_warnings);
+    changes.extend(get_sidecar_changes(&sidecars, &moves, &root, &extensions)?);
+    let plan = Plan { moves, changes };
+
+    let mode = match (dry_run, interactive) {
+        (true, _) => Mode::DryRun,
+        (_, true) => {
+            // The warnings are needed to decide whether to go ahead.
+            print_warnings(&std::mem::take(&mut warnings), verbosity, &mut io::stderr())?;
+            Mode::Interactive
+        }
+        _ => Mode::Apply,
+    };
+    let opts = ApplyOptions {
+        root: &root,
+        git,
+        make_dirs,
+        verbosity,
+    };
+    let result = execute(
+        plan,
+        mode,
+        &opts,
+        &mut warnings,
+        &mut io::stdin().lock(),
+        &mut io::stdout(),
+    );
+    print_warnings(&warnings, verbosity, &mut io::stderr())?;
+    result?;
+    if strict && link_warnings > 0 {
+        return Err(anyhow!(
+            "{link_warnings} link warning(s) emitted with --strict"
+        ));
+    }
+    Ok(())
+}
+
+/// The moves to make and the new content of every file whose links change.
+struct Plan {
+    moves: MoveList,
+    changes: ChangeList,
+}
+
+/// What to do with a [`Plan`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Only print the plan.
+    DryRun,
+    /// Print the plan and only carry it out if the user confirms.
+    Interactive,
+    /// Carry out the plan.
+    Apply,
+}
+
+struct ApplyOptions<'a> {
+    root: &'a Path,
+    git: bool,
+    make_dirs: bool,
+    verbosity: Verbosity,
+}
+
+/// Carries out the plan according to the mode.
+/// In interactive mode, the answer to the prompt is read from `input`.
+fn execute(
+    plan: Plan,
+    mode: Mode,
+    opts: &ApplyOptions,
+    warnings: &mut Vec<String>,
+    input: &mut impl io::BufRead,
+    output: &mut impl io::Write,
+) -> Result<()> {
+    if mode != Mode::Apply {
+        write!(
+            output,
+            "{}",
+            render_plan(&plan.moves, &plan.changes, opts.root)
+        )?;
+    }
+    match mode {
+        Mode::DryRun => return Ok(()),
+        Mode::Interactive => {
+            write!(output, "Apply these changes? [y/N] ")?;
+            output.flush()?;
+            let mut answer = String::new();
+            input.read_line(&mut answer)?;
+            if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+                return Err(anyhow!("aborted, nothing was changed"));
+            }
         }
+        Mode::Apply => {}
     }
+    if opts.make_dirs {
+        make_parent_dirs(&plan.moves)?;
+    }
+    apply_changes(
+        plan.moves,
+        plan.changes,
+        opts.root,
+        opts.git,
+        opts.verbosity,
+        warnings,
+    )
+}
 
-    let moves = get_move_list(sources, destination)?;
-    let changes = get_change_list(root.read_dir()?, &moves, &root)?;
+fn print_warnings(
+    warnings: &[String],
+    verbosity: Verbosity,
+    out: &mut impl io::Write,
+) -> io::Result<()> {
+    if verbosity == Verbosity::Quiet {
+        return Ok(());
+    }
+    for warning in warnings {
+        writeln!(out, "warning: {warning}")?;
+    }
+    Ok(())
+}
 
-    for (source, destination) in moves.0 {
-        println!("moving {source:#?} to {destination:#?}");
-        if !dry_run {
-            fs::rename(source, destination)?;
+fn apply_changes(
+    moves: MoveList,
+    changes: ChangeList,
+    root: &Path,
+    git: bool,
+    verbosity: Verbosity,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    let mut status = match git.then(|| git_status(root)) {
+        Some(Ok(status)) => Some(status),
+        Some(Err(err)) => {
+            warnings.push(format!("not using git: {err}"));
+            None
+        }
+        None => None,
+    };
+    let mut moves: Vec<_> = moves.0.into_iter().collect();
+    // Anything moved out of a moved directory goes before the directory itself.
+    moves.sort_by(|(a, _), (b, _)| {
+        let depth = |path: &PathBuf| path.components().count();
+        depth(b).cmp(&depth(a)).then_with(|| a.cmp(b))
+    });
+    for (source, destination) in moves {
+        if verbosity >= Verbosity::Normal {
+            println!("moving {source:#?} to {destination:#?}");
         }
+        if let Some(status) = &mut status {
+            if status.untracked.contains(&source) {
+                warnings.push(format!(
+                    "falling back to a plain move: {source:#?} isn't tracked by git"
+                ));
+            } else {
+                match run_git(
+                    root,
+                    &["mv".as_ref(), source.as_ref(), destination.as_ref()],
+                ) {
+                    Ok(()) => {
+                        if status.modified.remove(&source) {
+                            status.modified.insert(destination);
+                        }
+                        continue;
+                    }
+                    Err(err) => warnings.push(format!("falling back to a plain move: {err}")),
+                }
+            }
+            // Git doesn't know about the file at its new path.
+            status.untracked.insert(destination.clone());
+        }
+        fs::rename(source, destination)?;
     }
 
     for (path, change) in changes {
-        println!("writing changes to {path:#?}");
-        if !dry_run {
-            fs::write(path, change)?;
+        if verbosity >= Verbosity::Verbose {
+            println!("writing changes to {path:#?}");
+        }
+        fs::write(&path, change)?;
+        if let Some(status) = &status {
+            if status.untracked.contains(&path) {
+                warnings.push(format!(
+                    "changes to {path:#?} not staged: it isn't tracked by git"
+                ));
+            } else if status.modified.contains(&path) {
+                // Staging it would also stage the changes which were already there.
+                warnings.push(format!(
+                    "changes to {path:#?} not staged: it had uncommitted changes"
+                ));
+            } else if let Err(err) = run_git(root, &["add".as_ref(), path.as_ref()]) {
+                warnings.push(format!("changes to {path:#?} not staged: {err}"));
+            }
         }
     }
     Ok(())
 }
 
-fn get_move_list(mut sources: Vec<PathBuf>, destination: PathBuf) -> Result<MoveList> {
+/// The files under the root which differ from the last commit.
+#[derive(Debug, Default)]
+struct GitStatus {
+    untracked: HashSet<PathBuf>,
+    /// Tracked files with staged or unstaged changes.
+    modified: HashSet<PathBuf>,
+}
+
+/// Reads the status of the files under `root` with `git status`.
+fn git_status(root: &Path) -> Result<GitStatus> {
+    // Porcelain paths are relative to the top of the repository.
+    let prefix = git_output(root, &["rev-parse".as_ref(), "--show-prefix".as_ref()])?;
+    let prefix = prefix.trim_end_matches('\n');
+    let output = git_output(
+        root,
+        &[
+            "status".as_ref(),
+            "--porcelain".as_ref(),
+            "-z".as_ref(),
+            "--untracked-files=all".as_ref(),
+            "--".as_ref(),
+            ".".as_ref(),
+        ],
+    )?;
+    let mut status = GitStatus::default();
+    let mut entries = output.split('\0').filter(|entry| !entry.is_empty());
+    while let Some(entry) = entries.next() {
+        let Some((code, path)) = entry.split_at_checked(3) else {
+            continue;
+        };
+        let path = root.join(path.strip_prefix(prefix).unwrap_or(path));
+        if code == "?? " {
+            status.untracked.insert(path);
+        } else {
+            status.modified.insert(path);
+        }
+        // Renames and copies are followed by the path they came from.
+        if code.starts_with(['R', 'C']) {
+            if let Some(from) = entries.next() {
+                status
+                    .modified
+                    .insert(root.join(from.strip_prefix(prefix).unwrap_or(from)));
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Creates the parent directories of every destination.
+fn make_parent_dirs(moves: &MoveList) -> Result<()> {
+    for destination in moves.0.values() {
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs a git command in the given directory.
+fn run_git(dir: &Path, args: &[&OsStr]) -> Result<()> {
+    git_output(dir, args).map(|_| ())
+}
+
+/// Runs a git command in the given directory, returning its output.
+fn git_output(dir: &Path, args: &[&OsStr]) -> Result<String> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(anyhow!(
+            "git {} failed: {}",
+            args[0].to_string_lossy(),
+            String::from_utf8_lossy(&output.stderr).trim(),
+        ))
+    }
+}
+
+/// Renders the moves and the files whose links will change,
+/// grouping the latter by whether the file itself is being moved
+/// or only links to a file being moved.
+fn render_plan(moves: &MoveList, changes: &ChangeList, root: &Path) -> String {
+    let display = |path: &Path| {
+        path.strip_prefix(root)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    };
+
+    let mut move_lines: Vec<_> = moves
+        .0
+        .iter()
+        .map(|(from, to)| format!("  {} -> {}\n", display(from), display(to)))
+        .collect();
+    move_lines.sort();
+    let (mut moved, mut linking): (Vec<_>, Vec<_>) =
+        changes.keys().partition(|path| moves.is_destination(path));
+    moved.sort();
+    linking.sort();
+
+    let mut out = String::from("moves:\n");
+    out.extend(move_lines);
+    out += "moved files with rewritten links:\n";
+    for path in moved {
+        // Writing to a string can't fail.
+        writeln!(out, "  {}", display(path)).unwrap();
+    }
+    out += "files with rewritten links to moved files:\n";
+    for path in linking {
+        writeln!(out, "  {}", display(path)).unwrap();
+    }
+    out
+}
+
+/// The names of the files `mdsummary` treats as a directory's index.
+const INDEX_NAMES: [&str; 2] = ["README.md", "index.md"];
+
+/// Warns about every index moved out of its directory
+/// when the directory would still contain other markdown files.
+/// Moving an index isn't an error, but it changes the directory's summary.
+fn orphaned_index_warnings(moves: &MoveList, extensions: &[&str]) -> Result<Vec<String>> {
+    let mut warnings = Vec::new();
+    for (source, destination) in &moves.0 {
+        let is_index = source
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| INDEX_NAMES.contains(&name));
+        let Some(dir) = source.parent() else {
+            continue;
+        };
+        if !is_index || !source.is_file() || destination.parent() == Some(dir) {
+            continue;
+        }
+        let mut remaining = false;
+        for entry in dir.read_dir()? {
+            let path = entry?.path();
+            if is_markdown(&path, extensions) && path.is_file() && !moves.0.contains_key(&path) {
+                remaining = true;
+                break;
+            }
+        }
+        if remaining {
+            warnings.push(format!(
+                "moving '{}' leaves '{}' without an index",
+                source.display(),
+                dir.display()
+            ));
+        }
+    }
+    warnings.sort();
+    Ok(warnings)
+}
+
+/// Expands every source which doesn't exist as a literal path
+/// but contains glob metacharacters, for shells which don't expand them.
+fn expand_sources(sources: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+    let mut expanded = Vec::new();
+    for source in sources {
+        let pattern = source.to_string_lossy();
+        if source.exists() || !pattern.contains(['*', '?', '[']) {
+            expanded.push(source);
+            continue;
+        }
+        let mut matches = glob::glob(&pattern)?.collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(anyhow!("{pattern:?} doesn't match any paths"));
+        }
+        expanded.append(&mut matches);
+    }
+    Ok(expanded)
+}
+
+fn check_source(source: &Path) -> Result<()> {
+    if !source.exists() {
+        return Err(anyhow!("{source:?} doesn't exist"));
+    }
+    if source.to_str().is_none() {
+        return Err(anyhow!("{source:?} isn't valid utf8"));
+    }
+    Ok(())
+}
+
+fn absolute_path(path: PathBuf) -> Result<PathBuf> {
+    if path.is_relative() {
+        Ok(normalize_path(&env::current_dir()?.join(path)))
+    } else {
+        Ok(path)
+    }
+}
+
+/// Reads a list of moves from a file.
+/// Each non-empty line is a tab separated source and destination pair.
+/// Lines starting with `#` are ignored.
+fn read_move_file(move_file: &Path) -> Result<MoveList> {
+    let content = fs::read_to_string(move_file)?;
+    let mut moves = MoveList::default();
+    for (line_num, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((source, destination)) = line.split_once('\t') else {
+            return Err(anyhow!(
+                "{move_file:?}:{}: expected a tab separated source and destination",
+                line_num + 1,
+            ));
+        };
+        let source = PathBuf::from(source.trim());
+        check_source(&source)?;
+        let destination = absolute_path(PathBuf::from(destination.trim()))?;
+        moves
+            .0
+            .extend(get_move_list(vec![source], destination, false)?.0);
+    }
+    Ok(moves)
+}
+
+/// When moving more than one source, the destination must be a directory,
+/// unless `make_dirs` is set and the destination doesn't exist yet.
+fn get_move_list(
+    mut sources: Vec<PathBuf>,
+    destination: PathBuf,
+    make_dirs: bool,
+) -> Result<MoveList> {
+    let destination = canonicalize_destination(destination)?;
     if sources.len() == 1 {
         // ok to unwrap because the length is checked above
         let source = sources.pop().unwrap().canonicalize()?;
@@ -105,14 +636,15 @@ fn get_move_list(mut sources: Vec<PathBuf>, destination: PathBuf) -> Result<Move
             .file_name()
             // ok to unwarp because canonicalized
             .unwrap();
-        let dest = if destination.exists() {
+        // A source moved onto itself, e.g. `mdmove a.md a.md`, stays put.
+        let dest = if destination.is_dir() && destination != source {
             destination.join(name)
         } else {
             destination
         };
         return Ok(MoveList::from_iter([(source, dest)]));
     }
-    if !destination.is_dir() {
+    if !destination.is_dir() && (!make_dirs || destination.exists()) {
         return Err(anyhow!("Target {destination:?} not a directory"));
     }
     let moves: MoveList = sources
@@ -133,120 +665,951 @@ fn get_move_list(mut sources: Vec<PathBuf>, destination: PathBuf) -> Result<Move
     Ok(moves)
 }
 
-fn get_change_list(dir: ReadDir, moves: &MoveList, root: &Path) -> Result<ChangeList> {
-    let mut change_list = ChangeList::new();
-    for entry in dir {
-        let mut file = entry?.path();
-        if file.is_symlink() {
-            file = file.canonicalize()?;
+/// Errors if a move would overwrite something already at its destination,
+/// e.g. moving `a.md` onto an existing `b.md`.
+fn check_overwrites(moves: &MoveList) -> Result<()> {
+    let mut existing: Vec<_> = moves
+        .0
+        .values()
+        .filter(|destination| destination.exists())
+        .collect();
+    existing.sort();
+    match existing.first() {
+        Some(destination) => Err(anyhow!(
+            "{destination:?} already exists, use --force to overwrite it"
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Removes the moves of files onto themselves, e.g. `mdmove a.md .`,
+/// returning their sources.
+/// The sources and destinations must be canonical, like those from [`get_move_list`].
+fn skip_self_moves(moves: &mut MoveList) -> Vec<PathBuf> {
+    let mut skipped = Vec::new();
+    moves.0.retain(|source, destination| {
+        let is_self_move = source == destination;
+        if is_self_move {
+            skipped.push(source.clone());
         }
-        if file.is_dir() {
-            let list = get_change_list(file.read_dir()?, moves, root)?;
-            change_list.extend(list);
-        } else if file.is_file() {
-            let list = change_file(&file, moves, root)?;
-            change_list.extend(list);
+        !is_self_move
+    });
+    skipped.sort();
+    skipped
+}
+
+/// Resolves any symlinks in the destination, like the sources,
+/// so that links are resolved against the real paths.
+/// A destination which doesn't exist yet has its nearest existing
+/// ancestor canonicalized instead.
+fn canonicalize_destination(destination: PathBuf) -> Result<PathBuf> {
+    let mut existing = destination.as_path();
+    let mut missing = Vec::new();
+    while !existing.exists() {
+        let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+            return Ok(destination);
+        };
+        missing.push(name);
+        existing = parent;
+    }
+    let mut canonical = existing.canonicalize()?;
+    canonical.extend(missing.into_iter().rev());
+    Ok(canonical)
+}
+
+/// Whether a character can't continue a path found in a sidecar,
+/// i.e. it's a `/` or can't be part of a file name, such as a quote.
+fn is_path_boundary(c: char) -> bool {
+    c == '/' || !(c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ' ' | '~'))
+}
+
+/// Replaces the paths of moved files in the files matching the globs,
+/// skipping markdown files, whose links are rewritten instead.
+/// Both paths relative to the root, e.g. `notes/a.md`,
+/// and absolute paths are replaced.
+fn get_sidecar_changes(
+    patterns: &[String],
+    moves: &MoveList,
+    root: &Path,
+    extensions: &[&str],
+) -> Result<ChangeList> {
+    // Longer paths are replaced first,
+    // so a moved directory doesn't clobber a moved file inside it.
+    let mut replacements = Vec::new();
+    for (source, destination) in &moves.0 {
+        if let (Ok(source_rel), Ok(destination_rel)) =
+            (source.strip_prefix(root), destination.strip_prefix(root))
+        {
+            replacements.push((
+                source_rel.to_string_lossy(),
+                destination_rel.to_string_lossy(),
+            ));
+        }
+        replacements.push((source.to_string_lossy(), destination.to_string_lossy()));
+    }
+    replacements.sort_by_key(|(source, _)| std::cmp::Reverse(source.len()));
+
+    let mut change_list = ChangeList::new();
+    for pattern in patterns {
+        for file in glob::glob(pattern)? {
+            let file = absolute_path(file?)?;
+            if is_markdown(&file, extensions) || !file.is_file() || moves.is_overwritten(&file) {
+                continue;
+            }
+            let content = fs::read_to_string(&file)?;
+            // Each path is only replaced once,
+            // so a destination containing a source isn't replaced again.
+            // Only whole paths are replaced, so `notes` isn't in `My notes`.
+            let mut new_content = String::with_capacity(content.len());
+            let mut rest = content.as_str();
+            let mut prev = None;
+            'outer: while !rest.is_empty() {
+                if prev.is_none_or(is_path_boundary) {
+                    for (source, destination) in &replacements {
+                        let Some(after) = rest.strip_prefix(source.as_ref()) else {
+                            continue;
+                        };
+                        if after.chars().next().is_none_or(is_path_boundary) {
+                            new_content += destination;
+                            prev = source.chars().last();
+                            rest = after;
+                            continue 'outer;
+                        }
+                    }
+                }
+                // ok to unwrap because `rest` isn't empty
+                let c = rest.chars().next().unwrap();
+                new_content.push(c);
+                prev = Some(c);
+                rest = &rest[c.len_utf8()..];
+            }
+            if new_content != content {
+                let file_dest = moves.get_path_after_move(&file).unwrap_or(file);
+                change_list.insert(file_dest, new_content);
+            }
         }
     }
     Ok(change_list)
 }
 
-fn change_file(file: &Path, moves: &MoveList, root: &Path) -> Result<ChangeList> {
-    let mut change_list = ChangeList::new();
-    if !matches!(
-        file.extension().and_then(|ext| ext.to_str()),
-        Some("md" | "markdown"),
-    ) {
-        return Ok(change_list);
-    }
-    let file_dest = moves
-        .get_path_after_move(file)
-        .unwrap_or_else(|| file.to_path_buf());
-    let file_dir = file.parent().unwrap();
-    let file_dest_dir = file_dest.parent().unwrap();
-
-    let content = fs::read_to_string(file)?;
-
-    let replacement = |link: &str| {
-        // 1. make link absolute based on current file dir or root
-        // 2. if link is to a file in the move list,
-        //    change the link an absolute address of where the file will be
-        //    after the moves
-        // 3. make the link relative to the file containing it after the moves
-        //      *(this may be the same as before the moves)*
-        //      Unless the link was absolute,
-        //      in which case make the link relative to the root
-        let (link_path, frag) = match link.split_once('#') {
-            Some((p, fragment)) => (p, Some(fragment)),
-            None => (link, None),
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// A temporary root holding the given empty directories and files,
+    /// each file given with its content.
+    fn temp_root(dirs: &[&str], files: &[(&str, &str)]) -> Result<(TempDir, PathBuf)> {
+        let tmp = TempDir::new()?;
+        let root = tmp.path().canonicalize()?;
+        for dir in dirs {
+            fs::create_dir_all(root.join(dir))?;
+        }
+        for (file, content) in files {
+            let path = root.join(file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(path, content)?;
+        }
+        Ok((tmp, root))
+    }
+
+    /// A context which verifies the rewritten links.
+    fn context<'a>(moves: &'a MoveList, root: &'a Path) -> RewriteContext<'a> {
+        RewriteContext {
+            verify: true,
+            ..RewriteContext::new(moves, root)
+        }
+    }
+
+    /// The rewrites and warnings of the moves, scanning the whole root.
+    fn plan_changes(ctx: &RewriteContext) -> Result<(ChangeList, Vec<String>)> {
+        let plan = plan_moves(ctx, &ScanOptions::default())?;
+        Ok((plan.rewrites, plan.warnings))
+    }
+
+    /// Plans moving the sources to the destination, both relative to the root.
+    fn plan_move(
+        root: &Path,
+        sources: &[&str],
+        destination: &str,
+    ) -> Result<(MoveList, ChangeList, Vec<String>)> {
+        let sources = sources.iter().map(|source| root.join(source)).collect();
+        let moves = get_move_list(sources, root.join(destination), false)?;
+        let (changes, warnings) = plan_changes(&context(&moves, root))?;
+        Ok((moves, changes, warnings))
+    }
+
+    /// Carries out the moves and changes without git.
+    fn apply(moves: MoveList, changes: ChangeList, root: &Path) -> Result<()> {
+        apply_changes(
+            moves,
+            changes,
+            root,
+            false,
+            Verbosity::Quiet,
+            &mut Vec::new(),
+        )
+    }
+
+    #[test]
+    fn move_from_file() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["archive"],
+            &[
+                ("notes/a.md", "[b](b.md)\n"),
+                ("notes/b.md", "[a](./a.md)\n"),
+                ("index.md", "[a](notes/a.md) [b](notes/b.md)\n"),
+            ],
+        )?;
+        let move_file = root.join("moves.tsv");
+        fs::write(
+            &move_file,
+            format!(
+                "# reorganisation plan\n{}\t{}\n\n{}\t{}\n",
+                root.join("notes/a.md").display(),
+                root.join("archive").display(),
+                root.join("notes/b.md").display(),
+                root.join("archive/c.md").display(),
+            ),
+        )?;
+
+        let moves = read_move_file(&move_file)?;
+        assert_eq!(moves.0.len(), 2);
+        let (changes, _) = plan_changes(&context(&moves, &root))?;
+        apply(moves, changes, &root)?;
+
+        assert!(!root.join("notes/a.md").exists());
+        assert_eq!(
+            fs::read_to_string(root.join("archive/a.md"))?,
+            "[b](c.md)\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("archive/c.md"))?,
+            "[a](a.md)\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("index.md"))?,
+            "[a](archive/a.md) [b](archive/c.md)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn link_base() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["src/reference"],
+            &[
+                ("src/intro.md", "# Intro\n"),
+                // Relative to the base, not `guide/`.
+                ("src/guide/start.md", "[intro](intro.md)\n"),
+            ],
+        )?;
+        let base = root.join("src");
+        let moves = get_move_list(vec![base.join("intro.md")], base.join("reference"), false)?;
+        let ctx = RewriteContext {
+            link_base: Some(&base),
+            ..context(&moves, &root)
         };
-        if link_path.is_empty() {
-            return Ok(None);
-        }
-        let link_path = Path::new(link_path);
-        let mut comps = link_path.components();
-        // get absolute path to linked file
-        let (link_path_abs, was_abs) = match comps.next() {
-            Some(Normal(str)) if str == "https:" || str == "http:" => return Ok(None),
-            Some(RootDir) => (root.join(comps.as_path()), true),
-            _ => (file_dir.join(link_path), false),
+        let (changes, _) = plan_changes(&ctx)?;
+
+        assert_eq!(
+            changes
+                .get(&base.join("guide/start.md"))
+                .map(String::as_str),
+            Some("[intro](reference/intro.md)\n"),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_plan() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["notes"],
+            &[
+                ("a.md", "[b](b.md)\n"),
+                ("b.md", "# B\n"),
+                ("c.md", "[a](a.md)\n"),
+                ("d.md", "[b](b.md) [c](c.md)\n"),
+                ("e.md", "[e](e.md)\n"),
+            ],
+        )?;
+        let (moves, changes, _) = plan_move(&root, &["a.md", "b.md"], "notes")?;
+
+        let expected = "\
+moves:
+  a.md -> notes/a.md
+  b.md -> notes/b.md
+moved files with rewritten links:
+files with rewritten links to moved files:
+  c.md
+  d.md
+";
+        assert_eq!(render_plan(&moves, &changes, &root), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn encoded_and_query_links() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &[],
+            &[
+                ("old/my note.md", "# Note\n"),
+                (
+                    "index.md",
+                    "[a](old/my%20note.md) [b](old/my%20note.md?plain=1#top)\n",
+                ),
+            ],
+        )?;
+        let (_, changes, _) = plan_move(&root, &["old"], "new dir")?;
+
+        assert_eq!(
+            changes.get(&root.join("index.md")).map(String::as_str),
+            Some("[a](new%20dir/my%20note.md) [b](new%20dir/my%20note.md?plain=1#top)\n"),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nested_moves() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["docs/b", "docs/ab"],
+            &[("docs/a/x.md", "# X\n"), ("docs/a/y.md", "# Y\n")],
+        )?;
+        let moves = MoveList::from_iter([
+            (root.join("docs/a"), root.join("docs/b/a")),
+            (root.join("docs/a/x.md"), root.join("docs/ab/x.md")),
+        ]);
+        apply(moves, ChangeList::new(), &root)?;
+        assert!(!root.join("docs/a").exists());
+        assert_eq!(fs::read_to_string(root.join("docs/ab/x.md"))?, "# X\n");
+        assert_eq!(fs::read_to_string(root.join("docs/b/a/y.md"))?, "# Y\n");
+        assert!(!root.join("docs/b/a/x.md").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn git_moves() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &[],
+            &[
+                ("notes/keep.md", "# Keep\n"),
+                ("a.md", "# A\n\nSome text to detect the rename.\n"),
+                ("b.md", "[a](a.md)\n"),
+                ("c.md", "[a](a.md)\n"),
+            ],
+        )?;
+        let git = |args: &[&str]| -> Result<String> {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(&root)
+                .args(["-c", "user.name=test", "-c", "user.email=test@test"])
+                .args(args)
+                .output()?;
+            assert!(output.status.success(), "git {args:?} failed");
+            Ok(String::from_utf8(output.stdout)?)
         };
-        let mut link_path_abs = normalize_path(&link_path_abs);
-        if !link_path_abs.exists() {
-            println!(
-                "warning: '{}' in '{}' doesn't exist",
-                link_path_abs.display(),
-                file.display(),
-            );
-            return Ok(None);
-        }
-        if let Some(link_path_post_move) = moves.get_path_after_move(&link_path_abs) {
-            link_path_abs = link_path_post_move
+        git(&["init", "-q"])?;
+        git(&["add", "."])?;
+        git(&["commit", "-q", "-m", "init"])?;
+        fs::write(root.join("untracked.md"), "# Untracked\n")?;
+        fs::write(root.join("c.md"), "[a](a.md)\n\nNot committed.\n")?;
+
+        let (moves, changes, _) = plan_move(&root, &["a.md", "untracked.md"], "notes")?;
+        let mut warnings = Vec::new();
+        apply_changes(
+            moves,
+            changes,
+            &root,
+            true,
+            Verbosity::Normal,
+            &mut warnings,
+        )?;
+
+        let status = git(&["status", "--porcelain"])?;
+        let mut status: Vec<_> = status.lines().collect();
+        status.sort();
+        assert_eq!(
+            status,
+            [
+                " M c.md",
+                "?? notes/untracked.md",
+                "M  b.md",
+                "R  a.md -> notes/a.md"
+            ]
+        );
+        assert_eq!(fs::read_to_string(root.join("b.md"))?, "[a](notes/a.md)\n");
+        // Untracked files are moved without git,
+        // and files with uncommitted changes aren't staged.
+        warnings.sort();
+        assert_eq!(
+            warnings,
+            [
+                format!(
+                    "changes to {:#?} not staged: it had uncommitted changes",
+                    root.join("c.md")
+                ),
+                format!(
+                    "falling back to a plain move: {:#?} isn't tracked by git",
+                    root.join("untracked.md")
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn footnote_links() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["notes"],
+            &[
+                ("source.md", "# Source\n"),
+                (
+                    "index.md",
+                    "Text[^1].\n\n[^1]: From [the source](source.md).\n",
+                ),
+            ],
+        )?;
+        let (_, changes, _) = plan_move(&root, &["source.md"], "notes")?;
+
+        assert_eq!(
+            changes.get(&root.join("index.md")).map(String::as_str),
+            Some("Text[^1].\n\n[^1]: From [the source](notes/source.md).\n"),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn table_cell_links() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["notes"],
+            &[
+                ("source.md", "# Source\n"),
+                (
+                    "index.md",
+                    "| name | link |\n| --- | --- |\n| source | [x](source.md) |\n",
+                ),
+            ],
+        )?;
+        let (_, changes, warnings) = plan_move(&root, &["source.md"], "notes")?;
+
+        assert_eq!(
+            changes.get(&root.join("index.md")).map(String::as_str),
+            Some("| name | link |\n| --- | --- |\n| source | [x](notes/source.md) |\n"),
+        );
+        assert!(warnings.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn extensions() -> Result<()> {
+        let (_tmp, root) = temp_root(&["notes"], &[("a.md", "# A\n"), ("b.mdx", "[a](a.md)\n")])?;
+        let (moves, changes, _) = plan_move(&root, &["a.md"], "notes")?;
+        assert!(changes.is_empty());
+
+        let extensions = ["md", "markdown", "mdx"];
+        let opts = ScanOptions {
+            extensions: &extensions,
+            ..ScanOptions::default()
         };
+        let changes = plan_moves(&context(&moves, &root), &opts)?.rewrites;
+        assert_eq!(
+            changes.get(&root.join("b.mdx")).map(String::as_str),
+            Some("[a](notes/a.md)\n"),
+        );
+        Ok(())
+    }
 
-        let new_link_path = if was_abs {
-            let path_rel = link_path_abs.strip_prefix(root).unwrap();
-            Path::new("/").join(path_rel)
-        } else {
-            diff_paths(link_path_abs, file_dest_dir).unwrap()
+    #[test]
+    fn make_dirs() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &[],
+            &[
+                ("a.md", "[b](b.md)\n"),
+                ("b.md", "[a](a.md)\n"),
+                ("index.md", "[a](a.md) [b](b.md)\n"),
+            ],
+        )?;
+        let sources = vec![root.join("a.md"), root.join("b.md")];
+        let destination = root.join("new/folder");
+
+        assert!(get_move_list(sources.clone(), destination.clone(), false).is_err());
+        let moves = get_move_list(sources, destination, true)?;
+        let (changes, _) = plan_changes(&context(&moves, &root))?;
+        make_parent_dirs(&moves)?;
+        apply(moves, changes, &root)?;
+
+        assert_eq!(
+            fs::read_to_string(root.join("new/folder/a.md"))?,
+            "[b](b.md)\n"
+        );
+        assert!(root.join("new/folder/b.md").is_file());
+        assert_eq!(
+            fs::read_to_string(root.join("index.md"))?,
+            "[a](new/folder/a.md) [b](new/folder/b.md)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unused_and_duplicate_definitions() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["notes"],
+            &[
+                ("a.md", "# A\n"),
+                (
+                    "unused.md",
+                    "[a][] and [b]\n\n[a]: a.md\n[b]: a.md\n[old]: a.md\n",
+                ),
+                ("duplicate.md", "[a]\n\n[a]: a.md\n[A]: a.md\n"),
+                // Only the files being rewritten are checked.
+                ("other.md", "[c]: other.md\n"),
+            ],
+        )?;
+        let (_, _, mut warnings) = plan_move(&root, &["a.md"], "notes")?;
+        warnings.sort();
+        assert_eq!(
+            warnings,
+            [
+                format!(
+                    "'[a]' in '{}' is defined more than once, only the first definition is used",
+                    root.join("duplicate.md").display()
+                ),
+                format!(
+                    "'[old]' in '{}' is defined but never used",
+                    root.join("unused.md").display()
+                ),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn warnings() -> Result<()> {
+        let (_tmp, root) = temp_root(&["notes"], &[("a.md", "[missing](missing.md)\n")])?;
+        let (_, _, warnings) = plan_move(&root, &["a.md"], "notes")?;
+        assert_eq!(
+            warnings,
+            [format!(
+                "'{}' in '{}' doesn't exist",
+                root.join("missing.md").display(),
+                root.join("a.md").display()
+            )]
+        );
+
+        let mut stderr = Vec::new();
+        print_warnings(&warnings, Verbosity::Normal, &mut stderr)?;
+        assert_eq!(
+            String::from_utf8(stderr)?,
+            format!("warning: {}\n", warnings[0])
+        );
+        let mut stderr = Vec::new();
+        print_warnings(&warnings, Verbosity::Quiet, &mut stderr)?;
+        assert!(stderr.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn orphaned_index() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &[],
+            &[
+                ("notes/index.md", "# Notes\n"),
+                ("notes/a.md", "# A\n"),
+                ("archive/README.md", "# Archive\n"),
+            ],
+        )?;
+
+        let moves = get_move_list(vec![root.join("notes/index.md")], root.clone(), false)?;
+        assert_eq!(
+            orphaned_index_warnings(&moves, &MARKDOWN_EXTENSIONS)?,
+            [format!(
+                "moving '{}' leaves '{}' without an index",
+                root.join("notes/index.md").display(),
+                root.join("notes").display()
+            )]
+        );
+
+        // Nothing is left behind, so nothing is orphaned.
+        let moves = get_move_list(vec![root.join("archive/README.md")], root.clone(), false)?;
+        assert!(orphaned_index_warnings(&moves, &MARKDOWN_EXTENSIONS)?.is_empty());
+        // Neither is anything orphaned when everything moves together.
+        let sources = vec![root.join("notes/index.md"), root.join("notes/a.md")];
+        let moves = get_move_list(sources, root.join("archive"), false)?;
+        assert!(orphaned_index_warnings(&moves, &MARKDOWN_EXTENSIONS)?.is_empty());
+        // Nor when the index is only renamed.
+        let moves = get_move_list(
+            vec![root.join("notes/index.md")],
+            root.join("notes/old.md"),
+            false,
+        )?;
+        assert!(orphaned_index_warnings(&moves, &MARKDOWN_EXTENSIONS)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn glob_sources() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["published"],
+            &[
+                ("drafts/a.md", "[b](b.md)\n"),
+                ("drafts/b.md", "# B\n"),
+                ("drafts/c.txt", "c\n"),
+                ("index.md", "[a](drafts/a.md)\n"),
+            ],
+        )?;
+
+        let sources = expand_sources(vec![root.join("drafts/*.md")])?;
+        assert_eq!(
+            sources,
+            [root.join("drafts/a.md"), root.join("drafts/b.md")]
+        );
+        assert!(expand_sources(vec![root.join("drafts/*.rs")]).is_err());
+
+        let moves = get_move_list(sources, root.join("published"), false)?;
+        let (changes, _) = plan_changes(&context(&moves, &root))?;
+        apply(moves, changes, &root)?;
+
+        assert!(root.join("published/a.md").is_file());
+        assert!(root.join("published/b.md").is_file());
+        assert!(root.join("drafts/c.txt").is_file());
+        assert_eq!(
+            fs::read_to_string(root.join("index.md"))?,
+            "[a](published/a.md)\n"
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_destination() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["real/dir"],
+            &[("a.md", "[b](b.md)\n"), ("b.md", "[a](a.md)\n")],
+        )?;
+        std::os::unix::fs::symlink(root.join("real/dir"), root.join("link"))?;
+
+        let (moves, changes, warnings) = plan_move(&root, &["a.md"], "link")?;
+        assert_eq!(moves.0[&root.join("a.md")], root.join("real/dir/a.md"));
+        assert!(warnings.is_empty(), "{warnings:?}");
+        apply(moves, changes, &root)?;
+
+        // The links are relative to the real directory, not the symlink.
+        assert_eq!(
+            fs::read_to_string(root.join("real/dir/a.md"))?,
+            "[b](../../b.md)\n"
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("b.md"))?,
+            "[a](real/dir/a.md)\n"
+        );
+
+        // A new name inside a symlinked directory is resolved too.
+        let moves = get_move_list(vec![root.join("b.md")], root.join("link/c.md"), false)?;
+        assert_eq!(moves.0[&root.join("b.md")], root.join("real/dir/c.md"));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn follow_symlinks() -> Result<()> {
+        let (_tmp, tmp) = temp_root(
+            &["root/notes"],
+            &[
+                ("root/a.md", "# A\n"),
+                ("outside/shared.md", "[a](/a.md)\n"),
+            ],
+        )?;
+        let root = tmp.join("root");
+        let outside = tmp.join("outside");
+        std::os::unix::fs::symlink(&outside, root.join("shared"))?;
+
+        let (moves, changes, _) = plan_move(&root, &["a.md"], "notes")?;
+        assert_eq!(
+            changes.get(&outside.join("shared.md")).map(String::as_str),
+            Some("[a](/notes/a.md)\n")
+        );
+
+        let opts = ScanOptions {
+            follow_symlinks: false,
+            ..ScanOptions::default()
         };
-        let mut new_link = new_link_path.to_string_lossy().to_string();
-        if let Some(fragment) = frag {
-            new_link += "#";
-            new_link += fragment;
+        let changes = plan_moves(&context(&moves, &root), &opts)?.rewrites;
+        assert!(changes.is_empty(), "{changes:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn existing_destination() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &[],
+            &[
+                ("a.md", "# A\n"),
+                ("b.md", "# B\n"),
+                ("index.md", "[a](a.md) [b](b.md)\n"),
+            ],
+        )?;
+
+        let (moves, changes, _) = plan_move(&root, &["a.md"], "b.md")?;
+        assert_eq!(moves.0[&root.join("a.md")], root.join("b.md"));
+        let err = check_overwrites(&moves).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{:?} already exists, use --force to overwrite it",
+                root.join("b.md")
+            )
+        );
+        // Overwriting, as with `--force`.
+        apply(moves, changes, &root)?;
+        assert!(!root.join("a.md").exists());
+        assert_eq!(fs::read_to_string(root.join("b.md"))?, "# A\n");
+        assert_eq!(
+            fs::read_to_string(root.join("index.md"))?,
+            "[a](b.md) [b](b.md)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn overwritten_links() -> Result<()> {
+        // The overwritten file is scanned before the moved file in one
+        // of these, whatever order the directory is listed in.
+        for (source, destination) in [("x.md", "sub/x.md"), ("sub/x.md", "x.md")] {
+            let (_tmp, root) = temp_root(
+                &["sub"],
+                &[
+                    ("c.md", "# C\n"),
+                    (source, "[c](/c.md)\n"),
+                    (destination, &format!("[x](/{source})\n")),
+                ],
+            )?;
+            let (moves, changes, _) = plan_move(&root, &[source], destination)?;
+            // The overwritten file's rewrite would replace the moved file's.
+            assert!(changes.is_empty(), "{changes:?}");
+            apply(moves, changes, &root)?;
+            assert_eq!(fs::read_to_string(root.join(destination))?, "[c](/c.md)\n");
         }
-        Ok(Some(new_link))
-    };
-    if let Cow::Owned(new_content) = replace_links(&content, replacement)? {
-        change_list.insert(file_dest, new_content);
-    };
-    Ok(change_list)
-}
+        Ok(())
+    }
 
-// From <https://github.com/rust-lang/cargo/blob/fede83ccf973457de319ba6fa0e36ead454d2e20/src/cargo/util/paths.rs#L61>
-pub fn normalize_path(path: &Path) -> PathBuf {
-    let mut components = path.components().peekable();
-    let mut ret = if let Some(c @ Component::Prefix(..)) = components.peek().cloned() {
-        components.next();
-        PathBuf::from(c.as_os_str())
-    } else {
-        PathBuf::new()
-    };
+    #[test]
+    fn self_moves() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &[],
+            &[
+                ("a.md", "# A\n"),
+                ("index.md", "[a](./a.md)\n"),
+                ("notes/b.md", "[a](../a.md)\n"),
+            ],
+        )?;
 
-    for component in components {
-        match component {
-            Component::Prefix(..) => unreachable!(),
-            Component::RootDir => {
-                ret.push(component.as_os_str());
-            }
-            Component::CurDir => {}
-            Component::ParentDir => {
-                ret.pop();
-            }
-            Component::Normal(c) => {
-                ret.push(c);
-            }
+        for destination in [root.clone(), root.join("a.md")] {
+            let mut moves = get_move_list(vec![root.join("a.md")], destination, false)?;
+            assert_eq!(skip_self_moves(&mut moves), [root.join("a.md")]);
+            assert!(moves.0.is_empty());
+            let ctx = RewriteContext {
+                link_style: LinkStyle::RootAbsolute,
+                ..context(&moves, &root)
+            };
+            let (changes, warnings) = plan_changes(&ctx)?;
+            assert!(changes.is_empty(), "{changes:?}");
+            assert!(warnings.is_empty(), "{warnings:?}");
         }
+
+        // Only the self move is skipped.
+        let mut moves = get_move_list(
+            vec![root.join("a.md"), root.join("notes/b.md")],
+            root.clone(),
+            false,
+        )?;
+        assert_eq!(skip_self_moves(&mut moves), [root.join("a.md")]);
+        assert_eq!(moves.0.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn sidecars() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["archive"],
+            &[
+                ("notes/a.md", "# A\n"),
+                ("notes/ab.md", "# AB\n"),
+                ("index.md", "notes/a.md\n"),
+            ],
+        )?;
+        fs::write(
+            root.join("links.toml"),
+            format!(
+                "a = \"notes/a.md\"\nab = \"notes/ab.md\"\nabs = \"{}\"\n",
+                root.join("notes/a.md").display()
+            ),
+        )?;
+
+        let moves = get_move_list(vec![root.join("notes/a.md")], root.join("archive"), false)?;
+        let patterns = [
+            root.join("*.toml").display().to_string(),
+            root.join("*.md").display().to_string(),
+        ];
+        let changes = get_sidecar_changes(&patterns, &moves, &root, &MARKDOWN_EXTENSIONS)?;
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            changes[&root.join("links.toml")],
+            format!(
+                "a = \"archive/a.md\"\nab = \"notes/ab.md\"\nabs = \"{}\"\n",
+                root.join("archive/a.md").display()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sidecar_path_boundaries() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["archive"],
+            &[
+                ("notes/x.md", "# X\n"),
+                (
+                    "links.toml",
+                    "title = \"My notes\"\nold = \"notes_old/x.md\"\nbak = \"notes.bak\"\n\
+                     new = \"notes/x.md\"\nabs = \"/notes/x.md\"\n",
+                ),
+            ],
+        )?;
+
+        let moves = get_move_list(vec![root.join("notes")], root.join("archive"), false)?;
+        let patterns = [root.join("*.toml").display().to_string()];
+        let changes = get_sidecar_changes(&patterns, &moves, &root, &MARKDOWN_EXTENSIONS)?;
+        assert_eq!(
+            changes[&root.join("links.toml")],
+            "title = \"My notes\"\nold = \"notes_old/x.md\"\nbak = \"notes.bak\"\n\
+             new = \"archive/notes/x.md\"\nabs = \"/archive/notes/x.md\"\n",
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn interactive() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["notes"],
+            &[("a.md", "# A\n"), ("index.md", "[a](a.md)\n")],
+        )?;
+        let plan = || -> Result<Plan> {
+            let (moves, changes, _) = plan_move(&root, &["a.md"], "notes")?;
+            Ok(Plan { moves, changes })
+        };
+        let opts = ApplyOptions {
+            root: &root,
+            git: false,
+            make_dirs: false,
+            verbosity: Verbosity::Quiet,
+        };
+
+        let mut output = Vec::new();
+        let result = execute(
+            plan()?,
+            Mode::Interactive,
+            &opts,
+            &mut Vec::new(),
+            &mut "n\n".as_bytes(),
+            &mut output,
+        );
+        assert!(result.is_err());
+        let output = String::from_utf8(output)?;
+        assert!(output.starts_with("moves:\n  a.md -> notes/a.md\n"));
+        assert!(output.ends_with("Apply these changes? [y/N] "));
+        assert!(root.join("a.md").is_file());
+        assert!(!root.join("notes/a.md").exists());
+        assert_eq!(fs::read_to_string(root.join("index.md"))?, "[a](a.md)\n");
+
+        execute(
+            plan()?,
+            Mode::Interactive,
+            &opts,
+            &mut Vec::new(),
+            &mut "y\n".as_bytes(),
+            &mut Vec::new(),
+        )?;
+        assert!(root.join("notes/a.md").is_file());
+        assert_eq!(
+            fs::read_to_string(root.join("index.md"))?,
+            "[a](notes/a.md)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn scan_dirs() -> Result<()> {
+        let (_tmp, root) = temp_root(
+            &["notes"],
+            &[
+                ("a.md", "[b](b.md)\n"),
+                ("b.md", "# B\n"),
+                ("docs/index.md", "[a](../a.md)\n"),
+                ("other/index.md", "[a](../a.md)\n"),
+            ],
+        )?;
+        let moves = get_move_list(vec![root.join("a.md")], root.join("notes"), false)?;
+        let scan_dirs = [root.join("docs")];
+        let opts = ScanOptions {
+            scan_dirs: &scan_dirs,
+            ..ScanOptions::default()
+        };
+        let changes = plan_moves(&context(&moves, &root), &opts)?.rewrites;
+        let mut files: Vec<_> = changes.keys().collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [&root.join("docs/index.md"), &root.join("notes/a.md")]
+        );
+        assert_eq!(changes[&root.join("docs/index.md")], "[a](../notes/a.md)\n");
+        assert_eq!(changes[&root.join("notes/a.md")], "[b](../b.md)\n");
+        Ok(())
+    }
+
+    #[test]
+    fn move_file_without_tab() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let move_file = tmp.path().join("moves.tsv");
+        fs::write(&move_file, "a.md b.md\n")?;
+        assert!(read_move_file(&move_file).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn strict() -> Result<()> {
+        let (_tmp, root) = temp_root(&["notes"], &[("b.md", "[c](c.md)\n"), ("c.md", "# C\n")])?;
+        let mdmove = |args: &[&Path]| {
+            let mut cli = vec![Path::new("mdmove"), Path::new("-q"), Path::new("-r"), &root];
+            cli.extend(args);
+            run(Cli::try_parse_from(cli)?)
+        };
+
+        let b = root.join("b.md");
+        mdmove(&[Path::new("--strict"), &b, &root.join("notes")])?;
+        assert_eq!(
+            fs::read_to_string(root.join("notes/b.md"))?,
+            "[c](../c.md)\n"
+        );
+
+        let a = root.join("a.md");
+        fs::write(&a, "[missing](missing.md)\n")?;
+        assert!(mdmove(&[Path::new("--strict"), &a, &root.join("notes")]).is_err());
+        // The changes are still made.
+        assert!(root.join("notes/a.md").exists());
+
+        // Without --strict, the same warning doesn't fail the move.
+        mdmove(&[&root.join("notes/a.md"), &root])?;
+
+        // Other warnings, e.g. about an orphaned index, don't fail the move.
+        fs::remove_file(root.join("a.md"))?;
+        fs::create_dir(root.join("docs"))?;
+        fs::write(root.join("docs/index.md"), "# Docs\n")?;
+        fs::write(root.join("docs/d.md"), "# D\n")?;
+        let index = root.join("docs/index.md");
+        mdmove(&[Path::new("--strict"), &index, &root.join("notes")])?;
+        assert!(root.join("notes/index.md").exists());
+        Ok(())
     }
-    ret
 }
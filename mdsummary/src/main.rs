@@ -1,72 +1,273 @@
 use anyhow::{bail, Result};
-use clap::Parser;
-use std::borrow::Cow;
+use clap::{Parser, ValueEnum};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::{env, fs};
 
+use mdutils::frontmatter::{split_frontmatter, Frontmatter};
 use mdutils::headings::get_title;
+use mdutils::paths::resolve_symlink_chain;
+use pathdiff::diff_paths;
+use serde_json::{json, Value};
 
 const SUMMARY_MD: &str = "SUMMARY.md";
+/// Supplies the title and weight of a directory without an index,
+/// without becoming a chapter itself.
+const FOLDER_MD: &str = ".folder.md";
+/// Lists the files and directories to leave out of the summary,
+/// using gitignore syntax.
+const IGNORE_FILE: &str = ".mdsummaryignore";
+/// The file names of a directory's index, unless others are given.
+const DEFAULT_INDEX_NAMES: [&str; 2] = ["README.md", "index.md"];
+/// Replaced with the generated list in a summary template.
+const SUMMARY_PLACEHOLDER: &str = "{{summary}}";
 
 #[derive(Parser)]
 struct Options {
+    /// The directories to summarise, defaulting to the current directory.
+    /// The SUMMARY.md is kept in the first,
+    /// and the contents of any others are merged into its top level.
     #[arg(id = "directory")]
-    dir: Option<PathBuf>,
+    dirs: Vec<PathBuf>,
     /// Update the SUMMARY.md, if it is out of date.
     #[arg(short, long)]
     update: bool,
+    /// Don't list the contents of directories deeper than this.
+    /// A depth of 0 only lists the top level entries.
+    #[arg(short, long)]
+    max_depth: Option<usize>,
+    /// The file name of the index to use when a directory contains more than one,
+    /// e.g. README.md when it has both a README.md and an index.md.
+    /// The others are listed as ordinary entries.
+    /// Without this, or if none of them has this name, having several is an error.
+    #[arg(short, long)]
+    prefer: Option<String>,
+    /// Warn about every file without a title heading,
+    /// whose title falls back to its file name.
+    #[arg(short, long)]
+    warn_missing_title: bool,
+    /// A file containing the text surrounding the generated list,
+    /// which replaces the `{{summary}}` placeholder.
+    /// Without this, the list is preceded by a `# Summary` heading.
+    #[arg(short, long)]
+    template: Option<PathBuf>,
+    /// Leave the files and directories matching this glob out of the summary.
+    /// Uses gitignore syntax and combines with any `.mdsummaryignore`.
+    /// Can be given more than once.
+    #[arg(short, long)]
+    ignore: Vec<String>,
+    /// Leave out directories without an index,
+    /// along with their contents, rather than listing them as draft chapters.
+    #[arg(long)]
+    index_required: bool,
+    /// A file name which makes a file its directory's index,
+    /// e.g. `_index.md`, replacing the default README.md and index.md.
+    /// Can be given more than once.
+    #[arg(short = 'n', long = "index-name")]
+    index_names: Vec<String>,
+    /// Where the summary is kept,
+    /// defaulting to a SUMMARY.md in the first directory.
+    /// The entries' paths are relative to it.
+    #[arg(short, long)]
+    summary: Option<PathBuf>,
+    /// With 'json', print the summary's tree of titles, paths and children
+    /// instead of checking or updating the SUMMARY.md.
+    #[arg(short, long, default_value = "md")]
+    format: Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Md,
+    Json,
+}
+
+/// Controls how the summary is built from the directory tree.
+#[derive(Debug, Default)]
+struct Settings {
+    max_depth: Option<usize>,
+    /// The file name of the index to use when a directory has several.
+    prefer: Option<String>,
+    /// Globs of the paths to leave out, in gitignore syntax.
+    ignore: Vec<String>,
+    /// Whether to leave out directories without an index.
+    index_required: bool,
+    /// The file names of a directory's index.
+    /// If empty, the default names are used.
+    index_names: Vec<String>,
+}
+impl Settings {
+    fn is_index_name(&self, name: &OsStr) -> bool {
+        if self.index_names.is_empty() {
+            DEFAULT_INDEX_NAMES.iter().any(|index| name == *index)
+        } else {
+            self.index_names.iter().any(|index| name == index.as_str())
+        }
+    }
 }
 
 #[allow(unused)]
 #[derive(Debug)]
 struct Node {
     title: String,
+    /// Taken from the `weight` frontmatter key.
+    weight: Option<i64>,
     path: Option<PathBuf>,
+    /// Whether the title fell back to the file name.
+    untitled: bool,
+    /// The file or directory the node was built from.
+    /// Orders nodes with equal titles, so the summary doesn't depend on
+    /// the order the filesystem lists directories in.
+    source: PathBuf,
     sub_nodes: Vec<Node>,
 }
 impl Node {
-    fn from_dir(dir: &Path, default_title: String) -> Result<Option<Self>> {
+    /// The depth of a directory is the depth of its node in the summary.
+    /// At the maximum depth, the directory's contents aren't read,
+    /// beyond checking it contains a markdown file.
+    /// The title and weight come from the directory's index if it has one,
+    /// otherwise from its `.folder.md` if it has one.
+    /// `ancestors` holds the canonical paths of the directories containing this one.
+    fn from_dir(
+        dir: &Path,
+        default_title: String,
+        settings: &Settings,
+        ignore: &Gitignore,
+        depth: usize,
+        ancestors: &mut HashSet<PathBuf>,
+    ) -> Result<Option<Self>> {
         let mut title = default_title;
+        let mut weight = None;
+        let mut untitled = false;
         let mut index_path = None;
         let mut sub_nodes = Vec::new();
-        for entry_res in fs::read_dir(dir)? {
-            let entry = entry_res?;
-            let fs_name = entry.file_name();
-            if fs_name == "README.md" || fs_name == "index.md" {
-                if index_path.is_some() {
-                    bail!("Two indexes present");
-                }
+        let entries = read_dir(dir, ignore)?;
+        let index = Self::find_index(dir, &entries, settings)?;
+        if settings.index_required && index.is_none() {
+            return Ok(None);
+        }
+        let folder_md = dir.join(FOLDER_MD);
+        if index.is_none() && folder_md.is_file() {
+            let (folder_title, folder_weight) = read_folder_file(&folder_md)?;
+            title = folder_title.unwrap_or(title);
+            weight = folder_weight;
+        }
+        // A collapsed directory is a single entry, without sub-nodes.
+        let collapsed = settings.max_depth.is_some_and(|max| depth >= max);
+        for (idx, entry) in entries.iter().enumerate() {
+            if index == Some(idx) {
                 let path = entry.path();
-                title = title_from_md_file(&path)?;
+                MdFile {
+                    title,
+                    weight,
+                    untitled,
+                } = read_md_file(&path)?;
                 index_path = Some(path);
-            } else if let Some(node) = Self::from_entry(&entry)? {
+            } else if collapsed {
+                continue;
+            } else if let Some(node) =
+                Self::from_entry(entry, settings, ignore, depth + 1, ancestors)?
+            {
                 sub_nodes.push(node);
             }
         }
-        if sub_nodes.is_empty() && index_path.is_none() {
+        let is_empty = match collapsed {
+            true => index_path.is_none() && !contains_markdown(&entries, ignore, ancestors)?,
+            false => sub_nodes.is_empty() && index_path.is_none(),
+        };
+        if is_empty {
             // Ignore directory if it doesn't contain any markdown files.
             Ok(None)
         } else {
             Ok(Some(Node {
                 title,
+                weight,
                 path: index_path,
+                untitled,
+                source: dir.to_path_buf(),
                 sub_nodes,
             }))
         }
     }
 
-    fn from_entry(entry: &fs::DirEntry) -> Result<Option<Node>> {
+    /// Returns the position of the directory's index in its entries, if it has one.
+    fn find_index(
+        dir: &Path,
+        entries: &[fs::DirEntry],
+        settings: &Settings,
+    ) -> Result<Option<usize>> {
+        let indexes: Vec<_> = entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| settings.is_index_name(&entry.file_name()))
+            .map(|(idx, _)| idx)
+            .collect();
+        match (indexes.as_slice(), &settings.prefer) {
+            ([], _) => Ok(None),
+            ([idx], _) => Ok(Some(*idx)),
+            (_, None) => bail!("Two indexes present in {}", dir.display()),
+            (_, Some(prefer)) => {
+                let Some(idx) = indexes
+                    .into_iter()
+                    .find(|idx| entries[*idx].file_name() == prefer.as_str())
+                else {
+                    bail!(
+                        "Two indexes present in {}, neither of which is {prefer}",
+                        dir.display()
+                    );
+                };
+                eprintln!(
+                    "warning: Two indexes present in {}, using {prefer}",
+                    dir.display(),
+                );
+                Ok(Some(idx))
+            }
+        }
+    }
+
+    fn from_entry(
+        entry: &fs::DirEntry,
+        settings: &Settings,
+        ignore: &Gitignore,
+        depth: usize,
+        ancestors: &mut HashSet<PathBuf>,
+    ) -> Result<Option<Node>> {
         let fs_name = entry.file_name();
         let path = entry.path();
-        let path_real = resolve_links(&path)?;
+        let path_real = resolve_symlink_chain(&path)?;
         let node = if path_real.is_dir() {
+            // A symlink to an ancestor would otherwise be followed forever.
+            // Other directories are listed however many symlinks lead to them.
+            let canonical = path_real.canonicalize()?;
+            if !ancestors.insert(canonical.clone()) {
+                eprintln!(
+                    "warning: {} leads back to a directory containing it, skipping it",
+                    path.display()
+                );
+                return Ok(None);
+            }
             let fs_name = fs_name.to_string_lossy().to_string();
-            return Self::from_dir(&path_real, fs_name);
-        } else if path.extension().is_some_and(|ext| ext == "md") && fs_name != "SUMMARY.md" {
+            let node = Self::from_dir(&path_real, fs_name, settings, ignore, depth, ancestors);
+            ancestors.remove(&canonical);
+            return node;
+        } else if path.extension().is_some_and(|ext| ext == "md")
+            && fs_name != SUMMARY_MD
+            && fs_name != FOLDER_MD
+        {
+            let MdFile {
+                title,
+                weight,
+                untitled,
+            } = read_md_file(&path_real)?;
             Self {
-                title: title_from_md_file(&path_real)?,
+                title,
+                weight,
+                source: path.clone(),
                 path: Some(path),
+                untitled,
                 sub_nodes: Vec::new(),
             }
         } else {
@@ -79,106 +280,794 @@ impl Node {
         for sub_node in &mut self.sub_nodes {
             sub_node.sort()
         }
-        self.sub_nodes.sort_by(|a, b| a.title.cmp(&b.title));
+        self.sub_nodes.sort_by(Self::cmp);
+    }
+
+    /// Weighted nodes come first, ordered by weight,
+    /// then the rest of the nodes ordered by title.
+    /// Ties are broken by the source path.
+    fn cmp(a: &Self, b: &Self) -> Ordering {
+        match (a.weight, b.weight) {
+            (Some(a_weight), Some(b_weight)) => a_weight.cmp(&b_weight),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+        .then_with(|| a.title.cmp(&b.title))
+        .then_with(|| a.source.cmp(&b.source))
+    }
+
+    fn untitled_paths<'a>(&'a self, out: &mut Vec<&'a Path>) {
+        if let (true, Some(path)) = (self.untitled, &self.path) {
+            out.push(path);
+        }
+        for node in &self.sub_nodes {
+            node.untitled_paths(out);
+        }
+    }
+
+    /// The path relative to `base`, the directory of the summary,
+    /// with forward slashes, as mdbook expects, even on Windows.
+    fn rendered_path(&self, base: &Path) -> Option<String> {
+        self.path.as_ref().map(|p| {
+            let relative = diff_paths(p, base).unwrap_or_else(|| p.clone());
+            relative.to_string_lossy().replace('\\', "/")
+        })
     }
 
-    fn render_to_md(&self, depth: usize, out: &mut String) {
-        let path = self
-            .path
-            .as_ref()
-            .map(|p| p.to_string_lossy())
-            .map(|p| p.to_string())
-            .unwrap_or_default();
+    fn render_to_md(&self, depth: usize, base: &Path, out: &mut String) {
+        let path = self.rendered_path(base).unwrap_or_default();
 
-        out.extend(std::iter::repeat("  ").take(depth));
+        out.extend(std::iter::repeat_n("  ", depth));
         *out += &format!("- [{}]({})\n", self.title, path);
 
         for node in &self.sub_nodes {
-            node.render_to_md(depth + 1, out);
+            node.render_to_md(depth + 1, base, out);
         }
     }
+
+    /// A directory without an index has a `null` path.
+    fn to_json(&self, base: &Path) -> Value {
+        json!({
+            "title": self.title,
+            "path": self.rendered_path(base),
+            "children": self
+                .sub_nodes
+                .iter()
+                .map(|node| node.to_json(base))
+                .collect::<Vec<_>>(),
+        })
+    }
 }
 
 #[derive(Debug)]
 struct Summary(Vec<Node>);
 impl Summary {
-    fn from_dir(dir: &Path) -> Result<Self> {
+    /// Paths matching the settings' ignore globs,
+    /// or the `.mdsummaryignore` in `dir` if there is one, are left out.
+    /// Symlinked directories are followed, unless they lead back to an ancestor.
+    fn from_dir(dir: &Path, settings: &Settings) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(dir);
+        let ignore_file = dir.join(IGNORE_FILE);
+        if ignore_file.is_file() {
+            if let Some(err) = builder.add(ignore_file) {
+                return Err(err.into());
+            }
+        }
+        for glob in &settings.ignore {
+            builder.add_line(None, glob)?;
+        }
+        let ignore = builder.build()?;
+
+        let mut ancestors = HashSet::from([dir.canonicalize()?]);
         let mut nodes = Vec::new();
-        for entry_res in fs::read_dir(dir)? {
-            if let Some(node) = Node::from_entry(&entry_res?)? {
+        for entry in read_dir(dir, &ignore)? {
+            if let Some(node) = Node::from_entry(&entry, settings, &ignore, 0, &mut ancestors)? {
                 nodes.push(node);
             }
         }
         Ok(Self(nodes))
     }
 
+    /// Merges the summaries of several directories into one.
+    /// Entries with the same title are kept apart, not merged.
+    fn from_dirs(dirs: &[PathBuf], settings: &Settings) -> Result<Self> {
+        let mut nodes = Vec::new();
+        for dir in dirs {
+            nodes.extend(Self::from_dir(dir, settings)?.0);
+        }
+        Ok(Self(nodes))
+    }
+
     fn sort(mut self) -> Self {
         for node in &mut self.0 {
             node.sort()
         }
-        self.0.sort_by(|a, b| a.title.cmp(&b.title));
+        self.0.sort_by(Node::cmp);
         self
     }
 
-    fn render_to_md(&self) -> String {
-        let mut out = "# Summary\n\n".to_string();
+    /// The paths of the files whose titles fell back to their file names.
+    fn untitled_paths(&self) -> Vec<&Path> {
+        let mut paths = Vec::new();
+        for node in &self.0 {
+            node.untitled_paths(&mut paths);
+        }
+        paths
+    }
+
+    /// Renders the summary as a JSON array of its top level entries,
+    /// each with a `title`, `path` and `children`.
+    fn render_to_json(&self, base: &Path) -> String {
+        let nodes: Vec<_> = self.0.iter().map(|node| node.to_json(base)).collect();
+        // ok to unwrap because a `Value` always serializes
+        serde_json::to_string_pretty(&nodes).unwrap()
+    }
+
+    /// Renders the summary into the template,
+    /// which must contain the `{{summary}}` placeholder.
+    /// The entries' paths are made relative to `base`, the directory of the summary.
+    fn render_to_md(&self, template: Option<&str>, base: &Path) -> Result<String> {
+        let mut list = String::new();
         for node in &self.0 {
-            node.render_to_md(0, &mut out);
+            node.render_to_md(0, base, &mut list);
+        }
+        match template {
+            Some(template) if template.contains(SUMMARY_PLACEHOLDER) => {
+                Ok(template.replace(SUMMARY_PLACEHOLDER, list.trim_end()))
+            }
+            Some(_) => bail!("The template doesn't contain {SUMMARY_PLACEHOLDER}"),
+            None => Ok(format!("# Summary\n\n{list}")),
         }
-        out
     }
 }
 
-fn title_from_md_file(path: &Path) -> Result<String> {
+/// An entry of a rendered summary, e.g. `  - [Title](path.md)`.
+#[derive(Debug, PartialEq, Eq)]
+struct Entry<'a> {
+    depth: usize,
+    title: &'a str,
+    path: &'a str,
+}
+
+/// Parses the entries of a rendered summary, ignoring any other lines.
+fn parse_summary(summary: &str) -> Vec<Entry<'_>> {
+    summary
+        .lines()
+        .filter_map(|line| {
+            let item = line.trim_start_matches(' ');
+            let depth = (line.len() - item.len()) / 2;
+            let (title, path) = item
+                .strip_prefix("- [")?
+                .strip_suffix(')')?
+                .rsplit_once("](")?;
+            Some(Entry { depth, title, path })
+        })
+        .collect()
+}
+
+/// Describes the first entry of the current summary
+/// which differs from the new summary, if any do.
+fn first_difference(current: &str, new: &str) -> Option<String> {
+    let current = parse_summary(current);
+    let new = parse_summary(new);
+    let idx = (0..current.len().max(new.len())).find(|&idx| current.get(idx) != new.get(idx))?;
+    let describe = |entry: &Entry| format!("'{}' ({})", entry.title, entry.path);
+    let num = idx + 1;
+    Some(match (current.get(idx), new.get(idx)) {
+        (Some(old), Some(new)) if old.title == new.title && old.path == new.path => format!(
+            "entry {num} {} should be at depth {} not {}",
+            describe(new),
+            new.depth,
+            old.depth
+        ),
+        (Some(old), Some(new)) => format!(
+            "entry {num} is {} but should be {}",
+            describe(old),
+            describe(new)
+        ),
+        (None, Some(new)) => format!("entry {num} {} is missing", describe(new)),
+        (Some(old), None) => format!("entry {num} {} shouldn't be there", describe(old)),
+        // unreachable because `find` ensures the entries differ
+        (None, None) => unreachable!(),
+    })
+}
+
+/// Returns the entries of a directory which aren't ignored.
+fn read_dir(dir: &Path, ignore: &Gitignore) -> Result<Vec<fs::DirEntry>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let is_dir = entry.file_type()?.is_dir();
+        if !ignore.matched(entry.path(), is_dir).is_ignore() {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Whether any of the entries is, or is a directory containing,
+/// a markdown file which would be listed in a summary.
+/// `ancestors` holds the canonical paths of the directories containing the entries.
+fn contains_markdown(
+    entries: &[fs::DirEntry],
+    ignore: &Gitignore,
+    ancestors: &mut HashSet<PathBuf>,
+) -> Result<bool> {
+    for entry in entries {
+        let fs_name = entry.file_name();
+        let path = entry.path();
+        let path_real = resolve_symlink_chain(&path)?;
+        if path_real.is_dir() {
+            let canonical = path_real.canonicalize()?;
+            if !ancestors.insert(canonical.clone()) {
+                continue;
+            }
+            let found = contains_markdown(&read_dir(&path_real, ignore)?, ignore, ancestors);
+            ancestors.remove(&canonical);
+            if found? {
+                return Ok(true);
+            }
+        } else if path.extension().is_some_and(|ext| ext == "md")
+            && fs_name != SUMMARY_MD
+            && fs_name != FOLDER_MD
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+struct MdFile {
+    title: String,
+    weight: Option<i64>,
+    /// Whether the title fell back to the file name.
+    untitled: bool,
+}
+
+/// Returns the title and weight of a markdown file.
+fn read_md_file(path: &Path) -> Result<MdFile> {
     let content = fs::read_to_string(path)?;
-    if let Some(title) = get_title(&content) {
-        Ok(title.to_string())
+    let (frontmatter, body) = split_frontmatter(&content);
+    let weight = get_weight(frontmatter.as_ref(), path)?;
+    let (title, untitled) = if let Some(title) = get_title(body) {
+        (title.to_string(), false)
     } else {
         let Some(name) = path.file_stem().and_then(OsStr::to_str) else {
             bail!("Can't generate a title from this path: {}", path.display())
         };
-        Ok(name.to_string())
-    }
+        (name.to_string(), true)
+    };
+    Ok(MdFile {
+        title,
+        weight,
+        untitled,
+    })
 }
 
-fn resolve_links(path: &Path) -> Result<Cow<'_, Path>> {
-    if path.is_symlink() {
-        let mut path = path.to_path_buf();
-        let mut is_link = true;
-        while is_link {
-            path = fs::read_link(&path)?;
-            is_link = path.is_symlink();
-        }
-        Ok(Cow::Owned(path))
-    } else {
-        Ok(Cow::Borrowed(path))
+/// Returns the title and weight from a `.folder.md`.
+/// The title is taken from the `title` frontmatter key or the first heading.
+fn read_folder_file(path: &Path) -> Result<(Option<String>, Option<i64>)> {
+    let content = fs::read_to_string(path)?;
+    let (frontmatter, body) = split_frontmatter(&content);
+    let weight = get_weight(frontmatter.as_ref(), path)?;
+    let title = frontmatter
+        .as_ref()
+        .and_then(|f| f.get("title"))
+        .or_else(|| get_title(body))
+        .map(str::to_string);
+    Ok((title, weight))
+}
+
+fn get_weight(frontmatter: Option<&Frontmatter>, path: &Path) -> Result<Option<i64>> {
+    match frontmatter.and_then(|f| f.get("weight")) {
+        Some(weight) => match weight.parse() {
+            Ok(weight) => Ok(Some(weight)),
+            Err(_) => bail!("Invalid weight '{weight}' in {}", path.display()),
+        },
+        None => Ok(None),
     }
 }
 
 fn main() -> Result<()> {
     let opts = Options::parse();
-    let mut dir = match opts.dir {
-        Some(dir) if dir.is_dir() => dir,
-        Some(file) => bail!("{} is not a directory.", file.display()),
-        None => env::current_dir()?,
+    let template = opts.template.as_ref().map(fs::read_to_string).transpose()?;
+    if let Some(file) = opts.dirs.iter().find(|dir| !dir.is_dir()) {
+        bail!("{} is not a directory.", file.display());
+    }
+    let roots = if opts.dirs.is_empty() {
+        vec![env::current_dir()?]
+    } else {
+        opts.dirs
+            .iter()
+            .map(|dir| dir.canonicalize())
+            .collect::<Result<_, _>>()?
+    };
+    let summary_path = opts.summary.unwrap_or_else(|| roots[0].join(SUMMARY_MD));
+    let summary_dir = match summary_path.parent() {
+        Some(parent) if parent != Path::new("") => parent.canonicalize()?,
+        _ => env::current_dir()?,
     };
-    env::set_current_dir(&dir)?;
-    let new_summary = Summary::from_dir(&PathBuf::from("."))?
-        .sort()
-        .render_to_md();
+    let settings = Settings {
+        max_depth: opts.max_depth,
+        prefer: opts.prefer,
+        ignore: opts.ignore,
+        index_required: opts.index_required,
+        index_names: opts.index_names,
+    };
+    let summary = Summary::from_dirs(&roots, &settings)?.sort();
+    if opts.warn_missing_title {
+        for path in summary.untitled_paths() {
+            eprintln!(
+                "warning: {} has no title, using its file name",
+                path.display()
+            );
+        }
+    }
+    if opts.format == Format::Json {
+        println!("{}", summary.render_to_json(&summary_dir));
+        return Ok(());
+    }
+    let new_summary = summary.render_to_md(template.as_deref(), &summary_dir)?;
 
-    dir.push(SUMMARY_MD);
+    let display = summary_path.display();
     if opts.update {
-        println!("Writing summary to {}", dir.display());
-        fs::write(SUMMARY_MD, new_summary).map_err(Into::into)
+        println!("Writing summary to {display}");
+        fs::write(&summary_path, new_summary).map_err(Into::into)
     } else {
-        let Ok(current_summary) = fs::read_to_string(SUMMARY_MD) else {
-            bail!("Couldn't find or open {}", dir.display());
+        let Ok(current_summary) = fs::read_to_string(&summary_path) else {
+            bail!("Couldn't find or open {display}");
         };
         if new_summary != current_summary {
             let diff = prettydiff::text::diff_lines(&current_summary, &new_summary);
-            bail!("{} is out of date\n{diff}", dir.display());
+            match first_difference(&current_summary, &new_summary) {
+                Some(first) => bail!("{display} is out of date, {first}\n{diff}"),
+                None => bail!("{display} is out of date\n{diff}"),
+            }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn titles(nodes: &[Node]) -> Vec<&str> {
+        nodes.iter().map(|node| node.title.as_str()).collect()
+    }
+
+    #[test]
+    fn weighted_sort() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let dir = tmp.path();
+        fs::write(dir.join("a.md"), "# Alpha\n")?;
+        fs::write(dir.join("b.md"), "---\nweight: 2\n---\n# Bravo\n")?;
+        fs::write(dir.join("c.md"), "# Charlie\n")?;
+        fs::write(dir.join("d.md"), "+++\nweight = -1\n+++\n# Delta\n")?;
+        fs::create_dir(dir.join("e"))?;
+        fs::write(dir.join("e/index.md"), "---\nweight: 1\n---\n# Echo\n")?;
+        fs::write(dir.join("e/z.md"), "# Zulu\n")?;
+        fs::write(dir.join("e/y.md"), "---\nweight: 10\n---\n# Yankee\n")?;
+
+        let summary = Summary::from_dir(dir, &Settings::default())?.sort();
+        assert_eq!(
+            titles(&summary.0),
+            ["Delta", "Echo", "Bravo", "Alpha", "Charlie"]
+        );
+        assert_eq!(titles(&summary.0[1].sub_nodes), ["Yankee", "Zulu"]);
+        Ok(())
+    }
+
+    #[test]
+    fn max_depth() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let dir = tmp.path();
+        fs::write(dir.join("a.md"), "# A\n")?;
+        fs::create_dir_all(dir.join("b/c/d"))?;
+        fs::write(dir.join("b/index.md"), "# B\n")?;
+        fs::write(dir.join("b/c/README.md"), "# C\n")?;
+        fs::write(dir.join("b/c/e.md"), "# E\n")?;
+        fs::write(dir.join("b/c/d/f.md"), "# F\n")?;
+        fs::create_dir_all(dir.join("g/h"))?;
+        fs::write(dir.join("g/h/i.md"), "# I\n")?;
+
+        let settings = Settings {
+            max_depth: Some(0),
+            ..Default::default()
+        };
+        let summary = Summary::from_dir(dir, &settings)?.sort();
+        assert_eq!(titles(&summary.0), ["A", "B", "g"]);
+        assert!(summary.0.iter().all(|node| node.sub_nodes.is_empty()));
+        assert_eq!(summary.0[1].path, Some(dir.join("b/index.md")));
+        assert_eq!(summary.0[2].path, None);
+
+        let settings = Settings {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        let summary = Summary::from_dir(dir, &settings)?.sort();
+        let b = &summary.0[1];
+        assert_eq!(titles(&b.sub_nodes), ["C"]);
+        let c = &b.sub_nodes[0];
+        assert_eq!(c.path, Some(dir.join("b/c/README.md")));
+        assert!(c.sub_nodes.is_empty());
+
+        // Files past the maximum depth aren't read.
+        fs::write(dir.join("b/c/d/f.md"), "---\nweight: heavy\n---\n# F\n")?;
+        assert!(Summary::from_dir(dir, &settings).is_ok());
+        let settings = Settings::default();
+        assert!(Summary::from_dir(dir, &settings).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn index_required() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let dir = tmp.path();
+        fs::write(dir.join("a.md"), "# A\n")?;
+        fs::create_dir_all(dir.join("b/c"))?;
+        fs::write(dir.join("b/README.md"), "# B\n")?;
+        fs::write(dir.join("b/c/d.md"), "# D\n")?;
+        fs::create_dir_all(dir.join("e/f"))?;
+        fs::write(dir.join("e/g.md"), "# G\n")?;
+        fs::write(dir.join("e/f/index.md"), "# F\n")?;
+
+        let summary = Summary::from_dir(dir, &Settings::default())?.sort();
+        assert_eq!(titles(&summary.0), ["A", "B", "e"]);
+        assert_eq!(titles(&summary.0[1].sub_nodes), ["c"]);
+
+        let settings = Settings {
+            index_required: true,
+            ..Default::default()
+        };
+        let summary = Summary::from_dir(dir, &settings)?.sort();
+        assert_eq!(titles(&summary.0), ["A", "B"]);
+        assert!(summary.0[1].sub_nodes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn index_names() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let dir = tmp.path();
+        fs::create_dir(dir.join("b"))?;
+        fs::write(dir.join("b/_index.md"), "# Bravo\n")?;
+        fs::write(dir.join("b/README.md"), "# Readme\n")?;
+        fs::write(dir.join("b/c.md"), "# C\n")?;
+
+        let settings = Settings {
+            index_names: vec!["_index.md".to_string()],
+            ..Default::default()
+        };
+        let summary = Summary::from_dir(dir, &settings)?.sort();
+        let b = &summary.0[0];
+        assert_eq!(b.title, "Bravo");
+        assert_eq!(b.path, Some(dir.join("b/_index.md")));
+        assert_eq!(titles(&b.sub_nodes), ["C", "Readme"]);
+
+        let summary = Summary::from_dir(dir, &Settings::default())?.sort();
+        let b = &summary.0[0];
+        assert_eq!(b.title, "Readme");
+        assert_eq!(titles(&b.sub_nodes), ["Bravo", "C"]);
+        Ok(())
+    }
+
+    #[test]
+    fn preferred_index() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let dir = tmp.path();
+        fs::create_dir(dir.join("a"))?;
+        fs::write(dir.join("a/README.md"), "# Readme\n")?;
+        fs::write(dir.join("a/index.md"), "# Index\n")?;
+        fs::write(dir.join("a/b.md"), "# B\n")?;
+
+        assert!(Summary::from_dir(dir, &Settings::default()).is_err());
+
+        let settings = Settings {
+            prefer: Some("README.md".to_string()),
+            ..Default::default()
+        };
+        let summary = Summary::from_dir(dir, &settings)?.sort();
+        let a = &summary.0[0];
+        assert_eq!(a.title, "Readme");
+        assert_eq!(a.path, Some(dir.join("a/README.md")));
+        assert_eq!(titles(&a.sub_nodes), ["B", "Index"]);
+
+        let settings = Settings {
+            prefer: Some("index.md".to_string()),
+            ..Default::default()
+        };
+        let summary = Summary::from_dir(dir, &settings)?.sort();
+        assert_eq!(summary.0[0].title, "Index");
+        assert_eq!(titles(&summary.0[0].sub_nodes), ["B", "Readme"]);
+
+        // The preferred name must be one of the index names.
+        fs::write(dir.join("a/_index.md"), "# Underscore\n")?;
+        let settings = Settings {
+            prefer: Some("README.md".to_string()),
+            index_names: vec!["_index.md".to_string(), "index.md".to_string()],
+            ..Default::default()
+        };
+        assert!(Summary::from_dir(dir, &settings).is_err());
+        let settings = Settings {
+            prefer: Some("_index.md".to_string()),
+            ..settings
+        };
+        let summary = Summary::from_dir(dir, &settings)?.sort();
+        assert_eq!(summary.0[0].title, "Underscore");
+        assert_eq!(titles(&summary.0[0].sub_nodes), ["B", "Index", "Readme"]);
+        Ok(())
+    }
+
+    #[test]
+    fn folder_metadata() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let dir = tmp.path();
+        fs::write(dir.join("a.md"), "# A\n")?;
+        fs::create_dir(dir.join("b"))?;
+        fs::write(
+            dir.join("b/.folder.md"),
+            "---\ntitle: Bravo\nweight: 1\n---\n",
+        )?;
+        fs::write(dir.join("b/c.md"), "# C\n")?;
+        fs::create_dir(dir.join("d"))?;
+        fs::write(dir.join("d/.folder.md"), "# Ignored\n")?;
+        fs::write(dir.join("d/index.md"), "# Delta\n")?;
+
+        let summary = Summary::from_dir(dir, &Settings::default())?.sort();
+        assert_eq!(titles(&summary.0), ["Bravo", "A", "Delta"]);
+        let b = &summary.0[0];
+        assert_eq!(b.path, None);
+        assert_eq!(titles(&b.sub_nodes), ["C"]);
+        assert!(summary.0[2].sub_nodes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn missing_titles() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let dir = tmp.path();
+        fs::write(dir.join("a.md"), "# A\n")?;
+        fs::write(dir.join("b.md"), "Just text.\n")?;
+        fs::create_dir(dir.join("c"))?;
+        fs::write(dir.join("c/index.md"), "## Not a title\n")?;
+        fs::write(dir.join("c/d.md"), "# D\n")?;
+
+        let summary = Summary::from_dir(dir, &Settings::default())?.sort();
+        assert_eq!(titles(&summary.0), ["A", "b", "index"]);
+        assert_eq!(
+            summary.untitled_paths(),
+            [dir.join("b.md"), dir.join("c/index.md")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn equal_titles() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let dir = tmp.path();
+        for name in ["c.md", "a.md", "b.md"] {
+            fs::write(dir.join(name), "# Same\n")?;
+        }
+
+        let mut summary = Summary::from_dir(dir, &Settings::default())?.sort();
+        let sources = |summary: &Summary| -> Vec<PathBuf> {
+            summary.0.iter().map(|node| node.source.clone()).collect()
+        };
+        let expected = [dir.join("a.md"), dir.join("b.md"), dir.join("c.md")];
+        assert_eq!(sources(&summary), expected);
+
+        summary.0.reverse();
+        assert_eq!(sources(&summary.sort()), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn first_differences() {
+        let current = "# Summary\n\n- [A](a.md)\n- [B](b/index.md)\n  - [C](b/c.md)\n";
+        assert_eq!(first_difference(current, current), None);
+        assert_eq!(
+            parse_summary(current)[2],
+            Entry {
+                depth: 1,
+                title: "C",
+                path: "b/c.md"
+            }
+        );
+
+        let inserted =
+            "# Summary\n\n- [A](a.md)\n- [B](b/index.md)\n  - [Ab](b/ab.md)\n  - [C](b/c.md)\n";
+        assert_eq!(
+            first_difference(current, inserted).as_deref(),
+            Some("entry 3 is 'C' (b/c.md) but should be 'Ab' (b/ab.md)")
+        );
+        let appended = format!("{current}- [D](d.md)\n");
+        assert_eq!(
+            first_difference(current, &appended).as_deref(),
+            Some("entry 4 'D' (d.md) is missing")
+        );
+        assert_eq!(
+            first_difference(&appended, current).as_deref(),
+            Some("entry 4 'D' (d.md) shouldn't be there")
+        );
+        let outdented = current.replace("  - [C]", "- [C]");
+        assert_eq!(
+            first_difference(current, &outdented).as_deref(),
+            Some("entry 3 'C' (b/c.md) should be at depth 0 not 1")
+        );
+    }
+
+    #[test]
+    fn ignore_file() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let dir = tmp.path();
+        fs::write(dir.join("a.md"), "# A\n")?;
+        fs::write(dir.join("draft.md"), "# Draft\n")?;
+        fs::create_dir_all(dir.join("templates"))?;
+        fs::write(dir.join("templates/page.md"), "# Page\n")?;
+        fs::create_dir_all(dir.join("b/templates"))?;
+        fs::write(dir.join("b/index.md"), "# B\n")?;
+        fs::write(dir.join("b/templates/page.md"), "# Page\n")?;
+        fs::write(dir.join(IGNORE_FILE), "# Not chapters\n/templates/\n")?;
+
+        let summary = Summary::from_dir(dir, &Settings::default())?.sort();
+        assert_eq!(titles(&summary.0), ["A", "B", "Draft"]);
+        // Only the root's templates directory is anchored.
+        assert_eq!(titles(&summary.0[1].sub_nodes), ["templates"]);
+
+        // Globs from the command line combine with the ignore file.
+        let settings = Settings {
+            ignore: vec!["draft.md".to_string(), "b/templates".to_string()],
+            ..Default::default()
+        };
+        let summary = Summary::from_dir(dir, &settings)?.sort();
+        assert_eq!(titles(&summary.0), ["A", "B"]);
+        assert!(summary.0[1].sub_nodes.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn merged_dirs() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let guide = tmp.path().join("guide");
+        let reference = tmp.path().join("reference");
+        fs::create_dir_all(guide.join("c"))?;
+        fs::create_dir_all(&reference)?;
+        fs::write(guide.join("a.md"), "# Overview\n")?;
+        fs::write(guide.join("c/index.md"), "# C\n")?;
+        fs::write(reference.join("a.md"), "# Overview\n")?;
+        fs::write(reference.join("b.md"), "---\nweight: 1\n---\n# B\n")?;
+
+        let summary =
+            Summary::from_dirs(&[guide.clone(), reference.clone()], &Settings::default())?.sort();
+        assert_eq!(titles(&summary.0), ["B", "C", "Overview", "Overview"]);
+        let paths: Vec<_> = summary.0.iter().map(|node| node.path.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                Some(reference.join("b.md")),
+                Some(guide.join("c/index.md")),
+                Some(guide.join("a.md")),
+                Some(reference.join("a.md")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn forward_slashes() -> Result<()> {
+        let node = |title: &str, path: &str, sub_nodes| Node {
+            title: title.to_string(),
+            weight: None,
+            path: Some(PathBuf::from(path)),
+            untitled: false,
+            source: PathBuf::from(path),
+            sub_nodes,
+        };
+        let summary = Summary(vec![node(
+            "Sub",
+            r"sub\README.md",
+            vec![node("File", r".\sub\nested\file.md", Vec::new())],
+        )]);
+        assert_eq!(
+            summary.render_to_md(Some("{{summary}}"), Path::new(""))?,
+            "- [Sub](sub/README.md)\n  - [File](./sub/nested/file.md)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn templates() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let dir = tmp.path();
+        fs::write(dir.join("a.md"), "# A\n")?;
+        let summary = Summary::from_dir(dir, &Settings::default())?.sort();
+        let list = "- [A](a.md)";
+
+        assert_eq!(
+            summary.render_to_md(None, dir)?,
+            format!("# Summary\n\n{list}\n")
+        );
+        let template = "# Contents\n\n[Introduction](intro.md)\n\n{{summary}}\n\n---\n[Glossary](glossary.md)\n";
+        assert_eq!(
+            summary.render_to_md(Some(template), dir)?,
+            format!("# Contents\n\n[Introduction](intro.md)\n\n{list}\n\n---\n[Glossary](glossary.md)\n")
+        );
+        assert!(summary.render_to_md(Some("# Summary\n"), dir).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn json_output() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let dir = tmp.path();
+        fs::create_dir_all(dir.join("sub"))?;
+        fs::write(dir.join("a.md"), "# A\n")?;
+        fs::write(dir.join("sub/b.md"), "# B\n")?;
+        let summary = Summary::from_dir(dir, &Settings::default())?.sort();
+
+        let json: Value = serde_json::from_str(&summary.render_to_json(dir))?;
+        assert_eq!(
+            json,
+            json!([
+                { "title": "A", "path": "a.md", "children": [] },
+                {
+                    "title": "sub",
+                    "path": null,
+                    "children": [{ "title": "B", "path": "sub/b.md", "children": [] }],
+                },
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn summary_in_subdirectory() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let notes = tmp.path().join("notes");
+        let book = tmp.path().join("book");
+        fs::create_dir_all(notes.join("sub"))?;
+        fs::create_dir_all(&book)?;
+        fs::write(notes.join("a.md"), "# A\n")?;
+        fs::write(notes.join("sub/b.md"), "# B\n")?;
+        let summary = Summary::from_dir(&notes, &Settings::default())?.sort();
+
+        assert_eq!(
+            summary.render_to_md(Some("{{summary}}"), &notes)?,
+            "- [A](a.md)\n- [sub]()\n  - [B](sub/b.md)"
+        );
+        assert_eq!(
+            summary.render_to_md(Some("{{summary}}"), &book)?,
+            "- [A](../notes/a.md)\n- [sub]()\n  - [B](../notes/sub/b.md)"
+        );
+        assert_eq!(
+            summary.render_to_md(Some("{{summary}}"), &notes.join("sub"))?,
+            "- [A](../a.md)\n- [sub]()\n  - [B](b.md)"
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_cycles() -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let tmp = TempDir::new()?;
+        let dir = tmp.path();
+        fs::write(dir.join("a.md"), "# A\n")?;
+        fs::create_dir(dir.join("b"))?;
+        fs::write(dir.join("b/c.md"), "# C\n")?;
+        symlink("..", dir.join("b/up"))?;
+        symlink(".", dir.join("b/here"))?;
+        // An alias of a sibling isn't a cycle, so both are listed.
+        symlink("b", dir.join("latest"))?;
+
+        let summary = Summary::from_dir(dir, &Settings::default())?.sort();
+        assert_eq!(titles(&summary.0), ["A", "b", "latest"]);
+        assert_eq!(titles(&summary.0[1].sub_nodes), ["C"]);
+        assert_eq!(titles(&summary.0[2].sub_nodes), ["C"]);
+        Ok(())
+    }
+}
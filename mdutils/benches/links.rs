@@ -0,0 +1,48 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use mdutils::links::{get_links, may_contain_links};
+
+/// A thousand notes, only one in ten of which contains a link.
+fn notes() -> Vec<String> {
+    (0..1000)
+        .map(|idx| {
+            let mut note = format!("# Note {idx}\n\n");
+            for _ in 0..20 {
+                note += "Some *emphasised* text, a [bracket] and `code`.\n";
+            }
+            if idx % 10 == 0 {
+                note += "\nSee [the next note](note.md).\n";
+            }
+            note
+        })
+        .collect()
+}
+
+fn find_links(c: &mut Criterion) {
+    let notes = notes();
+    c.bench_function("get_links", |b| {
+        b.iter(|| {
+            notes
+                .iter()
+                .map(|note| get_links(note).len())
+                .sum::<usize>()
+        })
+    });
+    c.bench_function("get_links with may_contain_links", |b| {
+        b.iter(|| {
+            notes
+                .iter()
+                .filter(|note| may_contain_links(note))
+                .map(|note| get_links(note).len())
+                .sum::<usize>()
+        })
+    });
+}
+
+criterion_group! {
+    name = benches;
+    // Parsing every note is slow, so fewer samples are taken.
+    config = Criterion::default().sample_size(10);
+    targets = find_links
+}
+criterion_main!(benches);
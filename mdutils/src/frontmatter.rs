@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+/// The top level key/value pairs of a document's frontmatter.
+///
+/// Only flat scalar values are recorded.
+/// Nested tables, lists and comments are ignored.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Frontmatter(BTreeMap<String, String>);
+impl Frontmatter {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+/// Splits a `---` delimited YAML or `+++` delimited TOML frontmatter block
+/// from the start of the document.
+/// Returns the parsed frontmatter, if there is any, and the remaining body.
+/// An unterminated block isn't considered frontmatter.
+/// A leading byte order mark is skipped.
+pub fn split_frontmatter(content: &str) -> (Option<Frontmatter>, &str) {
+    let bom_len = if content.starts_with('\u{feff}') {
+        '\u{feff}'.len_utf8()
+    } else {
+        0
+    };
+    let mut lines = content[bom_len..].split_inclusive('\n');
+    let Some(first) = lines.next() else {
+        return (None, content);
+    };
+    let (delimiter, separator) = match first.trim_end() {
+        "---" => ("---", ':'),
+        "+++" => ("+++", '='),
+        _ => return (None, content),
+    };
+
+    let mut offset = bom_len + first.len();
+    let mut map = BTreeMap::new();
+    for line in lines {
+        offset += line.len();
+        let line = line.trim_end();
+        if line == delimiter {
+            return (Some(Frontmatter(map)), &content[offset..]);
+        }
+        // Indented lines belong to a nested value.
+        if line.starts_with(char::is_whitespace) || line.starts_with('#') {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once(separator) {
+            let value = unquote(value.trim());
+            if !value.is_empty() {
+                map.insert(key.trim().to_string(), value.to_string());
+            }
+        }
+    }
+    (None, content)
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn yaml_frontmatter() {
+        let input = "---\ntitle: \"Hello: World\"\nweight: 3\ntags:\n  - a\n---\n# Body\n";
+        let (frontmatter, body) = split_frontmatter(input);
+        let frontmatter = frontmatter.unwrap();
+        assert_eq!(frontmatter.get("title"), Some("Hello: World"));
+        assert_eq!(frontmatter.get("weight"), Some("3"));
+        assert_eq!(frontmatter.get("tags"), None);
+        assert_eq!(body, "# Body\n");
+    }
+
+    #[test]
+    fn toml_frontmatter() {
+        let input = "+++\r\ntitle = 'Hello'\r\ndraft = true\r\n+++\r\nbody";
+        let (frontmatter, body) = split_frontmatter(input);
+        let frontmatter = frontmatter.unwrap();
+        assert_eq!(frontmatter.get("title"), Some("Hello"));
+        assert_eq!(frontmatter.get("draft"), Some("true"));
+        assert_eq!(body, "body");
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let input = "\u{feff}---\r\ntitle: Hello\r\n---\r\n# Body\r\n";
+        let (frontmatter, body) = split_frontmatter(input);
+        assert_eq!(frontmatter.unwrap().get("title"), Some("Hello"));
+        assert_eq!(body, "# Body\r\n");
+
+        let input = "\u{feff}# Body\n";
+        assert_eq!(split_frontmatter(input), (None, input));
+    }
+
+    #[test]
+    fn no_frontmatter() {
+        let input = "# Title\n---\nfoo: bar\n---\n";
+        assert_eq!(split_frontmatter(input), (None, input));
+        assert_eq!(split_frontmatter(""), (None, ""));
+    }
+
+    #[test]
+    fn unterminated_frontmatter() {
+        let input = "---\ntitle: Hello\n# Body\n";
+        assert_eq!(split_frontmatter(input), (None, input));
+    }
+}
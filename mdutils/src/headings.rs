@@ -1,29 +1,158 @@
-use tree_sitter::{Query, QueryCursor};
-use tree_sitter_md::MarkdownParser;
+use core::ops::Range;
+use std::collections::HashMap;
 
-/// Extracts the first atx heading at level 1 in the document
-/// Returning the raw markdown of the title if found.
-pub fn get_title(input: &str) -> Option<&str> {
-    let tree = {
-        let mut parser = MarkdownParser::default();
-        parser.parse(input.as_bytes(), None).unwrap()
-    };
-    let block_query = Query::new(
+use once_cell::sync::Lazy;
+use tree_sitter::{Node, Query, QueryCursor};
+
+static HEADING_QUERY: Lazy<Query> = Lazy::new(|| {
+    Query::new(
         &tree_sitter_md::language(),
-        "(atx_heading (atx_h1_marker) (inline) @title)",
+        "[(atx_heading) (setext_heading)] @heading",
     )
-    .unwrap();
+    .unwrap()
+});
+
+/// Returns the level and raw markdown of every heading in the document,
+/// in document order, e.g. `(2, "Title")` for `## Title`.
+///
+/// Both atx and setext headings are included,
+/// but not those nested in another block, such as a block quote or list.
+/// The closing sequence of an atx heading,
+/// e.g. the trailing `#` in `# Title #`, is removed.
+pub fn get_titles(input: &str) -> Vec<(u8, &str)> {
+    let tree = crate::parse_tree(input);
 
     QueryCursor::new()
         .matches(
-            &block_query,
+            &HEADING_QUERY,
             tree.block_tree().root_node(),
             input.as_bytes(),
         )
-        .next()
-        .and_then(|matches| matches.captures.first())
+        .filter_map(|matches| matches.captures.first())
         .map(|capture| capture.node)
-        .map(|node| &input[node.byte_range()])
+        .filter(|node| is_top_level(*node))
+        .filter_map(|heading| {
+            let mut level = None;
+            let mut text = None;
+            for idx in 0..heading.named_child_count() {
+                let Some(child) = heading.named_child(idx) else {
+                    continue;
+                };
+                match child.kind() {
+                    "inline" => text = Some(child),
+                    // The content of a setext heading is a paragraph.
+                    "paragraph" => text = child.named_child(0),
+                    kind => {
+                        level = level.or_else(|| heading_level(kind));
+                    }
+                }
+            }
+            let text = &input[text?.byte_range()];
+            let text = match heading.kind() {
+                "atx_heading" => strip_closing_sequence(text),
+                _ => text.trim(),
+            };
+            Some((level?, text))
+        })
+        .collect()
+}
+
+/// The level of a heading from the kind of its marker or underline.
+fn heading_level(marker: &str) -> Option<u8> {
+    match marker {
+        "setext_h1_underline" => Some(1),
+        "setext_h2_underline" => Some(2),
+        _ => marker
+            .strip_prefix("atx_h")?
+            .strip_suffix("_marker")?
+            .parse()
+            .ok(),
+    }
+}
+
+/// Removes the optional closing sequence of `#`s of an atx heading,
+/// which must be preceded by a space unless it's all there is.
+fn strip_closing_sequence(text: &str) -> &str {
+    let text = text.trim();
+    let without_hashes = text.trim_end_matches('#');
+    if without_hashes.is_empty() {
+        without_hashes
+    } else if without_hashes.ends_with([' ', '\t']) {
+        without_hashes.trim_end()
+    } else {
+        text
+    }
+}
+
+/// Extracts the title of the document,
+/// returning the raw markdown of the title if found.
+///
+/// The title is the first level 1 heading returned by [`get_titles`],
+/// e.g. `Title` for `# Title #` or `Title\n=====`.
+pub fn get_title(input: &str) -> Option<&str> {
+    get_titles(input)
+        .into_iter()
+        .find(|(level, _)| *level == 1)
+        .map(|(_, title)| title)
+}
+
+/// Returns the anchor of a heading, e.g. `hello-world` for `Hello, World!`,
+/// like mdbook does.
+///
+/// Alphanumeric characters, `_` and `-` are kept and lowercased,
+/// whitespace becomes `-` and everything else is dropped.
+/// The heading should be plain text,
+/// as markup, e.g. the destination of a link, isn't removed.
+pub fn heading_to_anchor(heading: &str) -> String {
+    heading
+        .trim()
+        .chars()
+        .filter_map(|c| {
+            if c.is_alphanumeric() || c == '_' || c == '-' {
+                Some(c.to_ascii_lowercase())
+            } else if c.is_whitespace() {
+                Some('-')
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Returns the byte range of the text of the heading with the given anchor,
+/// e.g. `Section` in `## Section` for `section`.
+///
+/// Headings are considered in document order, as in [`get_titles`],
+/// and a heading with the same anchor as an earlier one has a count appended,
+/// e.g. the second `## Notes` is `notes-1`.
+pub fn find_heading_by_anchor(content: &str, anchor: &str) -> Option<Range<usize>> {
+    let mut seen = HashMap::new();
+    get_titles(content).into_iter().find_map(|(_, title)| {
+        let base = heading_to_anchor(title);
+        let count = seen.entry(base.clone()).or_insert(0);
+        let heading_anchor = match *count {
+            0 => base,
+            count => format!("{base}-{count}"),
+        };
+        *count += 1;
+        (heading_anchor == anchor).then(|| {
+            // The title is a slice of the content.
+            let start = title.as_ptr() as usize - content.as_ptr() as usize;
+            start..start + title.len()
+        })
+    })
+}
+
+/// Whether the block is only nested in sections.
+fn is_top_level(block: Node) -> bool {
+    let mut parent = block.parent();
+    while let Some(node) = parent {
+        if !matches!(node.kind(), "section" | "document") {
+            return false;
+        }
+        parent = node.parent();
+    }
+    true
 }
 
 #[cfg(test)]
@@ -44,8 +173,83 @@ not another one!
 
 ## sanity returns
 # why at the bottom?";
-        let actual = get_title(&input);
-        assert_eq!(actual, Some("why at the bottom?"));
+        let actual = get_title(input);
+        assert_eq!(actual, Some("not another one!"));
+        assert_eq!(get_title("## Two\n# One #\n"), Some("One"));
         Ok(())
     }
+
+    #[test]
+    fn all_headings() {
+        let input = "\
+# One #
+
+Setext
+======
+
+## Two
+
+> # Quoted
+
+Sub
+---
+
+###### C# ####
+####### Not a heading
+";
+        assert_eq!(
+            get_titles(input),
+            [(1, "One"), (1, "Setext"), (2, "Two"), (2, "Sub"), (6, "C#")]
+        );
+    }
+
+    #[test]
+    fn anchors() {
+        assert_eq!(heading_to_anchor("Hello, World!"), "hello-world");
+        assert_eq!(
+            heading_to_anchor(" snake_case and-dashes "),
+            "snake_case-and-dashes"
+        );
+        // Like mdbook, only ascii is lowercased.
+        assert_eq!(heading_to_anchor("Ünïcode 2"), "Ünïcode-2");
+
+        let input = "\
+# Notes
+
+## Hello, World!
+
+## Notes
+
+Notes
+-----
+";
+        let heading = |anchor| find_heading_by_anchor(input, anchor).map(|range| &input[range]);
+        assert_eq!(heading("hello-world"), Some("Hello, World!"));
+        let ranges: Vec<_> = ["notes", "notes-1", "notes-2"]
+            .into_iter()
+            .map(|anchor| find_heading_by_anchor(input, anchor).unwrap().start)
+            .collect();
+        assert_eq!(ranges, [2, 30, 37]);
+        assert_eq!(heading("notes-3"), None);
+        assert_eq!(heading("missing"), None);
+    }
+
+    #[test]
+    fn nested_headings() {
+        let input = "\
+> # Quoted
+
+- # Listed
+
+```
+# Code
+```
+
+# Title
+
+# Second
+";
+        assert_eq!(get_title(input), Some("Title"));
+        assert_eq!(get_title("> # Quoted\n"), None);
+    }
 }
@@ -0,0 +1,116 @@
+use core::ops::Range;
+use std::borrow::Cow;
+
+use crate::links::{replace_ranges, BoxError, LinkError};
+
+/// The mdbook directives which take a path.
+const DIRECTIVES: [&str; 3] = ["include", "playground", "rustdoc_include"];
+
+/// Returns the byte range of the path of every mdbook include directive,
+/// i.e. `{{#include file.rs}}`, `{{#playground file.rs}}`
+/// and `{{#rustdoc_include file.rs}}`, in document order.
+///
+/// The ranges exclude any line numbers or anchor, e.g. `:10:20` or `:anchor`,
+/// and any attributes, e.g. `editable`.
+/// Directives escaped with a backslash, e.g. `\{{#include file.rs}}`,
+/// aren't processed by mdbook, so are skipped.
+/// Directives are found anywhere, including in code blocks, like mdbook.
+pub fn get_include_paths(content: &str) -> Vec<Range<usize>> {
+    let mut paths = Vec::new();
+    let mut cursor = 0;
+    while let Some(idx) = content[cursor..].find("{{#") {
+        let start = cursor + idx;
+        cursor = start + 3;
+        if content[..start].ends_with('\\') {
+            continue;
+        }
+        let Some(len) = content[cursor..].find("}}") else {
+            break;
+        };
+        let directive = &content[cursor..cursor + len];
+        let name_len = directive
+            .find(char::is_whitespace)
+            .unwrap_or(directive.len());
+        if !DIRECTIVES.contains(&&directive[..name_len]) {
+            continue;
+        }
+        let args = &directive[name_len..];
+        let path_start = cursor + name_len + (args.len() - args.trim_start().len());
+        let path_len = content[path_start..cursor + len]
+            .find(|c: char| c == ':' || c.is_whitespace())
+            .unwrap_or(cursor + len - path_start);
+        if path_len > 0 {
+            paths.push(path_start..path_start + path_len);
+        }
+        cursor += len + 2;
+    }
+    paths
+}
+
+/// Like [`replace_links`](crate::links::replace_links),
+/// but replaces the paths of mdbook include directives.
+pub fn replace_include_paths(
+    content: &str,
+    replacement: impl Fn(&str) -> Result<Option<String>, BoxError>,
+) -> Result<Cow<'_, str>, LinkError> {
+    replace_ranges(
+        content,
+        get_include_paths(content),
+        &mut Vec::new(),
+        |range| replacement(&content[range]),
+    )
+    .map(|(new_content, _)| new_content)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn include_paths(content: &str) -> Vec<&str> {
+        get_include_paths(content)
+            .into_iter()
+            .map(|range| &content[range])
+            .collect()
+    }
+
+    #[test]
+    fn directives() {
+        let content = "\
+{{#include file.rs}}
+```rust
+{{#include ../code/lines.rs:10:20}}
+{{#include ../code/from.rs:10:}}
+{{#include ../code/to.rs::20}}
+{{#rustdoc_include  anchored.rs:main }}
+```
+{{#playground example.rs editable}}
+{{#playground ./example.rs:2}}
+\\{{#include escaped.rs}}
+{{#title Not a path}}
+{{#include}}
+";
+        assert_eq!(
+            include_paths(content),
+            [
+                "file.rs",
+                "../code/lines.rs",
+                "../code/from.rs",
+                "../code/to.rs",
+                "anchored.rs",
+                "example.rs",
+                "./example.rs",
+            ]
+        );
+    }
+
+    #[test]
+    fn replace_paths() -> Result<(), LinkError> {
+        let content = "{{#include a.rs:anchor}} [a](a.rs)\n{{#playground b.rs editable}}\n";
+        let actual = replace_include_paths(content, |path| Ok(Some(format!("src/{path}"))))?;
+        assert_eq!(
+            actual,
+            "{{#include src/a.rs:anchor}} [a](a.rs)\n{{#playground src/b.rs editable}}\n"
+        );
+        Ok(())
+    }
+}
@@ -1,2 +1,69 @@
+pub mod frontmatter;
 pub mod headings;
+pub mod includes;
 pub mod links;
+pub mod moves;
+pub mod paths;
+pub mod report;
+pub mod text;
+
+use thiserror::Error;
+use tree_sitter_md::{MarkdownParser, MarkdownTree};
+
+/// The parser gave up before producing a tree.
+#[derive(Error, Debug)]
+#[error("failed to parse the markdown")]
+pub struct ParseError;
+
+/// Parses markdown, including the GitHub Flavored Markdown extensions,
+/// i.e. tables, strikethrough and task lists, which tree-sitter-md
+/// always enables. Every function in this library parses with this,
+/// so links and text are found in the same constructs by all the tools.
+///
+/// tree-sitter only gives up when cancelled or timed out,
+/// neither of which is set here, but the error is returned rather than panicking.
+pub fn parse(input: &str) -> Result<MarkdownTree, ParseError> {
+    MarkdownParser::default()
+        .parse(input.as_bytes(), None)
+        .ok_or(ParseError)
+}
+
+/// Like [`parse`], for the functions of this library which can't fail.
+pub(crate) fn parse_tree(input: &str) -> MarkdownTree {
+    // ok to unwrap because parsing only fails if cancelled or timed out
+    parse(input).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tree_sitter::Node;
+
+    fn add_kinds(node: Node, kinds: &mut Vec<&'static str>) {
+        kinds.push(node.kind());
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            add_kinds(child, kinds);
+        }
+    }
+
+    #[test]
+    fn gfm_nodes() -> Result<(), ParseError> {
+        let tree = parse("| a |\n| --- |\n| b |\n\n~~c~~\n\n- [x] d\n- [ ] e\n")?;
+        let mut kinds = Vec::new();
+        add_kinds(tree.block_tree().root_node(), &mut kinds);
+        for inline_tree in tree.inline_trees() {
+            add_kinds(inline_tree.root_node(), &mut kinds);
+        }
+        for kind in [
+            "pipe_table",
+            "pipe_table_cell",
+            "strikethrough",
+            "task_list_marker_checked",
+            "task_list_marker_unchecked",
+        ] {
+            assert!(kinds.contains(&kind), "no {kind} in {kinds:?}");
+        }
+        Ok(())
+    }
+}
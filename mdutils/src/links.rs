@@ -1,86 +1,1044 @@
 use core::ops::Range;
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
 
-use anyhow::Result;
-use tree_sitter::{Query, QueryCursor};
-use tree_sitter_md::MarkdownParser;
+use once_cell::sync::Lazy;
+use thiserror::Error;
+use tree_sitter::{Node, Query, QueryCursor};
+use tree_sitter_md::MarkdownTree;
+use url::Url;
+
+use crate::paths::{decode_link_path, encode_link_path, resolve_link, split_link};
+
+// Compiling a query is far slower than running it, so they're compiled once.
+// There are two different tree types needed to express a markdown document.
+// A top level 'block' tree and a number of inline trees.
+// We need a different query for each.
+static LINK_BLOCK_QUERY: Lazy<Query> =
+    Lazy::new(|| Query::new(&tree_sitter_md::language(), "(link_destination) @link").unwrap());
+static LINK_INLINE_QUERY: Lazy<Query> = Lazy::new(|| {
+    Query::new(
+        &tree_sitter_md::inline_language(),
+        "[(link_destination) (uri_autolink)] @link",
+    )
+    .unwrap()
+});
+static HTML_BLOCK_QUERY: Lazy<Query> =
+    Lazy::new(|| Query::new(&tree_sitter_md::language(), "(html_block) @html").unwrap());
+static HTML_INLINE_QUERY: Lazy<Query> =
+    Lazy::new(|| Query::new(&tree_sitter_md::inline_language(), "(html_tag) @html").unwrap());
+static REFERENCE_INLINE_QUERY: Lazy<Query> = Lazy::new(|| {
+    Query::new(
+        &tree_sitter_md::inline_language(),
+        "[
+            (shortcut_link (link_text) @label @text)
+            (collapsed_reference_link (link_text) @label @text)
+            (full_reference_link (link_text) @text (link_label) @label)
+        ] @reference",
+    )
+    .unwrap()
+});
+static DEFINITION_QUERY: Lazy<Query> = Lazy::new(|| {
+    Query::new(
+        &tree_sitter_md::language(),
+        "(link_reference_definition
+            (link_label) @label
+            (link_destination) @destination
+            (link_title)? @title
+        ) @definition",
+    )
+    .unwrap()
+});
+static INLINE_LINK_QUERY: Lazy<Query> = Lazy::new(|| {
+    Query::new(
+        &tree_sitter_md::inline_language(),
+        "[
+            (inline_link (link_text)? @text (link_destination)? @destination (link_title)? @title)
+            (image (image_description)? @text (link_destination)? @destination (link_title)? @title)
+        ] @link",
+    )
+    .unwrap()
+});
+
+/// Which links to return from [`get_links_in_scope`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkScope {
+    All,
+    /// Links to paths, e.g. `../notes/foo.md`.
+    LocalOnly,
+    /// Links with a scheme, e.g. `https://hugom.uk` or `mailto:me@hugom.uk`,
+    /// or protocol relative links, e.g. `//hugom.uk`.
+    ExternalOnly,
+}
+impl LinkScope {
+    pub fn includes(self, link: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::LocalOnly => is_local_link(link),
+            Self::ExternalOnly => is_external_link(link),
+        }
+    }
+}
+
+/// Whether the link has a scheme or is protocol relative.
+pub fn is_external_link(link: &str) -> bool {
+    !is_local_link(link)
+}
+
+/// Whether the link is a path, e.g. `./foo.md`, `../foo.md` or `/foo.md`.
+/// Windows paths, e.g. `C:\foo.md` or `..\foo.md`, are also local,
+/// even though a drive letter looks like a URL scheme.
+pub fn is_local_link(link: &str) -> bool {
+    if link.starts_with("//") {
+        return false;
+    }
+    let mut chars = link.chars();
+    let has_drive_letter = matches!(
+        (chars.next(), chars.next(), chars.next()),
+        (Some(letter), Some(':'), Some('\\' | '/')) if letter.is_ascii_alphabetic()
+    );
+    has_drive_letter || Url::parse(link).is_err()
+}
+
+/// Where a link was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    /// The destination of an inline link, e.g. `[foo](foo.md)`.
+    Inline,
+    /// The destination of an image, e.g. `![foo](foo.png)`.
+    Image,
+    /// The destination of a link reference definition, e.g. `[foo]: foo.md`.
+    Definition,
+    /// An autolink, e.g. `<https://hugom.uk>`.
+    Autolink,
+    /// The `href` or `src` attribute of raw HTML, e.g. `<a href="foo.md">`.
+    Html,
+}
+
+/// A link found by [`get_links_with_kind`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The byte range of the link's destination.
+    pub range: Range<usize>,
+    pub kind: LinkKind,
+    /// The byte range of the text of an inline link or image,
+    /// e.g. `foo` in `[foo](foo.md)`, if it has any.
+    pub text: Option<Range<usize>>,
+}
+
+/// A cheap check for whether the input markdown might contain a link,
+/// which is much faster than parsing it.
+/// False positives are fine, but there are no false negatives:
+/// if this returns `false`, [`get_links`] returns nothing
+/// and there are no mdbook include directives.
+pub fn may_contain_links(input: &str) -> bool {
+    // Inline links and images contain `](`, definitions `]:`,
+    // and autolinks and raw HTML start with `<`.
+    ["](", "]:", "<", "{{#"]
+        .iter()
+        .any(|pattern| input.contains(pattern))
+}
 
 /// Returns the byte range of every link found in the input markdown.
+/// The ranges cover only the destination, excluding any surrounding
+/// whitespace, so links wrapped onto their own line are handled,
+/// and any angle brackets, e.g. `<foo bar.md>`.
+/// The `href` and `src` attributes of raw HTML are included.
 /// The returned vector may not be ordered.
 pub fn get_links(input: &str) -> Vec<Range<usize>> {
-    let tree = {
-        let mut parser = MarkdownParser::default();
-        parser.parse(input.as_bytes(), None).unwrap()
-    };
+    get_links_with_kind(input)
+        .into_iter()
+        .map(|link| link.range)
+        .collect()
+}
+
+/// Like [`get_links`], but also says where each link was found.
+pub fn get_links_with_kind(input: &str) -> Vec<Link> {
+    let tree = crate::parse_tree(input);
+    // ok to unwrap because the tree was parsed from the input
+    get_links_in_tree(&tree, input).unwrap()
+}
+
+/// Like [`get_links`], but also finds the links in markdown nested inside
+/// raw HTML blocks, such as MDX components, e.g. `[notes](notes.md)` in
+/// `<Callout>\n  Read [notes](notes.md).\n</Callout>`.
+///
+/// CommonMark, and so [`get_links`], treats everything up to the next blank line
+/// after an HTML tag starting a line as raw HTML, only finding `href` and `src`
+/// attributes in it. Here the tags are blanked out and what's left is parsed
+/// as markdown. This is a heuristic rather than an MDX parser, so it can also
+/// find links which wouldn't be rendered, e.g. in a JSX expression, and links
+/// which a CommonMark renderer would show as plain text.
+///
+/// Unlike [`get_links`], the returned vector is in document order.
+pub fn get_links_lenient(input: &str) -> Vec<Range<usize>> {
+    let tree = crate::parse_tree(input);
+    let mut links = get_links(input);
     let mut query_cur = QueryCursor::new();
+    let html_blocks = query_cur.matches(
+        &HTML_BLOCK_QUERY,
+        tree.block_tree().root_node(),
+        input.as_bytes(),
+    );
+    for html in html_blocks.flat_map(|matches| matches.captures.iter().map(|c| c.node)) {
+        let start = html.start_byte();
+        let inner = mask_html_tags(&input[html.byte_range()]);
+        links.extend(
+            get_links_with_kind(&inner)
+                .into_iter()
+                .filter(|link| link.kind != LinkKind::Html)
+                .map(|link| (start + link.range.start)..(start + link.range.end)),
+        );
+    }
+    links.sort_by_key(|range| range.start);
+    links
+}
 
-    // There are two different tree types needed to express a markdown document.
-    // A top level 'block' tree and a number of inline trees.
-    // We need a different query for each.
-    let block_query = Query::new(&tree_sitter_md::language(), "(link_destination) @link").unwrap();
-    let inline_query = Query::new(
-        &tree_sitter_md::inline_language(),
-        "[(link_destination) (uri_autolink)] @link",
-    )
-    .unwrap();
+/// Replaces every HTML or JSX tag, e.g. `<Chart data={[1, 2]} />`, with spaces,
+/// keeping line breaks, so the rest of the content keeps its byte offsets.
+fn mask_html_tags(html: &str) -> String {
+    let mut bytes = html.as_bytes().to_vec();
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let is_tag_start = bytes[idx] == b'<'
+            && bytes
+                .get(idx + 1)
+                .is_some_and(|b| b.is_ascii_alphabetic() || matches!(b, b'/' | b'!' | b'>'));
+        if !is_tag_start {
+            idx += 1;
+            continue;
+        }
+        // Quotes and braces, e.g. `onClick={() => a > b}`, don't end the tag.
+        let mut quote = None;
+        let mut depth = 0usize;
+        while idx < bytes.len() {
+            let byte = bytes[idx];
+            match (quote, byte) {
+                (Some(q), _) if byte == q => quote = None,
+                (Some(_), _) => {}
+                (None, b'"' | b'\'' | b'`') => quote = Some(byte),
+                (None, b'{') => depth += 1,
+                (None, b'}') => depth = depth.saturating_sub(1),
+                (None, b'>') if depth == 0 => {
+                    bytes[idx] = b' ';
+                    idx += 1;
+                    break;
+                }
+                _ => {}
+            }
+            if byte != b'\n' {
+                bytes[idx] = b' ';
+            }
+            idx += 1;
+        }
+    }
+    // ok to unwrap because only whole ASCII delimited tags were replaced,
+    // so every multibyte character was either kept or entirely replaced
+    String::from_utf8(bytes).unwrap()
+}
+
+/// Returns each distinct link destination in the input markdown once,
+/// in the order they first appear.
+pub fn get_unique_link_destinations(input: &str) -> Vec<String> {
+    let mut links = get_links(input);
+    links.sort_by_key(|range| range.start);
+    let mut seen = HashSet::new();
+    links
+        .into_iter()
+        .map(|range| input[range].trim())
+        .filter(|link| seen.insert(*link))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Finds the links in a tree parsed from `input`.
+/// Errors, rather than panicking, if a node doesn't fit the input,
+/// which means the tree was parsed from different content.
+fn get_links_in_tree(tree: &MarkdownTree, input: &str) -> Result<Vec<Link>, LinkError> {
+    let mut query_cur = QueryCursor::new();
 
     // Find the matches in the block tree.
     let block_matches = query_cur.matches(
-        &block_query,
+        &LINK_BLOCK_QUERY,
         tree.block_tree().root_node(),
         input.as_bytes(),
     );
     // Find all the matches in the inline trees.
     let inline_matches = tree.inline_trees().iter().flat_map(|inline_tree| {
-        query_cur.matches(&inline_query, inline_tree.root_node(), input.as_bytes())
+        query_cur.matches(
+            &LINK_INLINE_QUERY,
+            inline_tree.root_node(),
+            input.as_bytes(),
+        )
     });
     // Convert the matches into the byte range of the link destination.
-    block_matches
-        .chain(inline_matches)
-        .flat_map(|matches| matches.captures.iter())
-        .map(|capture| capture.node)
-        .map(|node| {
-            // If it's an auto link, e.g. `<https://hugom.uk>`,
-            // we need want to remove the angle brackets.
-            if node.kind() == "uri_autolink" {
-                let range = node.byte_range();
-                (range.start + 1)..(range.end - 1)
-            } else {
-                node.byte_range()
+    let nodes = block_matches
+        .map(|matches| (matches, LinkKind::Definition))
+        .chain(inline_matches.map(|matches| (matches, LinkKind::Inline)))
+        .flat_map(|(matches, kind)| matches.captures.iter().map(move |c| (c.node, kind)));
+    let mut links = Vec::new();
+    for (node, kind) in nodes {
+        let text = node_text(input, node)?;
+        if is_footnote_definition(input, node)? {
+            continue;
+        }
+        // If it's an auto link, e.g. `<https://hugom.uk>`,
+        // we need want to remove the angle brackets.
+        let link = if node.kind() == "uri_autolink" {
+            let range = node.byte_range();
+            Link {
+                range: (range.start + 1)..(range.end - 1),
+                kind: LinkKind::Autolink,
+                text: None,
+            }
+        } else {
+            let is_image = node.parent().is_some_and(|parent| parent.kind() == "image");
+            Link {
+                range: strip_angle_brackets(input, trim_range(text, node.byte_range())),
+                kind: if is_image { LinkKind::Image } else { kind },
+                text: link_text(node),
             }
+        };
+        links.push(link);
+    }
+
+    // Links in raw HTML aren't parsed by tree-sitter, so are scanned for.
+    let html_blocks = query_cur
+        .matches(
+            &HTML_BLOCK_QUERY,
+            tree.block_tree().root_node(),
+            input.as_bytes(),
+        )
+        .flat_map(|matches| matches.captures.iter().map(|c| c.node))
+        .collect::<Vec<_>>();
+    let html_tags = tree.inline_trees().iter().flat_map(|inline_tree| {
+        query_cur
+            .matches(
+                &HTML_INLINE_QUERY,
+                inline_tree.root_node(),
+                input.as_bytes(),
+            )
+            .flat_map(|matches| matches.captures.iter().map(|c| c.node))
+            .collect::<Vec<_>>()
+    });
+    for html in html_blocks.into_iter().chain(html_tags) {
+        let start = html.start_byte();
+        links.extend(
+            get_html_links(node_text(input, html)?)
+                .into_iter()
+                .map(|range| Link {
+                    range: (start + range.start)..(start + range.end),
+                    kind: LinkKind::Html,
+                    text: None,
+                }),
+        );
+    }
+    Ok(links)
+}
+
+/// Returns the byte range of the text of the inline link or image
+/// whose destination is the given node.
+fn link_text(destination: Node) -> Option<Range<usize>> {
+    let parent = destination.parent()?;
+    let mut cursor = parent.walk();
+    let text = parent
+        .children(&mut cursor)
+        .find(|child| matches!(child.kind(), "link_text" | "image_description"));
+    text.map(|text| text.byte_range())
+}
+
+/// Returns the text of the node,
+/// or an error if the node doesn't fit the input.
+fn node_text<'a>(input: &'a str, node: Node) -> Result<&'a str, LinkError> {
+    input
+        .get(node.byte_range())
+        .ok_or_else(|| LinkError::OutOfBounds {
+            kind: node.kind(),
+            range: node.byte_range(),
+            len: input.len(),
         })
-        .collect()
 }
 
-/// Will only error if `replacement` returns an error.
+/// Returns the index of the first byte at or after `idx` not matching `f`.
+fn skip_while(bytes: &[u8], mut idx: usize, f: impl Fn(&u8) -> bool) -> usize {
+    while bytes.get(idx).is_some_and(&f) {
+        idx += 1;
+    }
+    idx
+}
+
+/// The attributes of raw HTML which hold links.
+const HTML_LINK_ATTRIBUTES: [&str; 2] = ["href", "src"];
+
+/// Returns the byte range of the value of every `href` and `src` attribute
+/// in the given HTML, which may be quoted or unquoted.
+/// This is a lightweight scan rather than a full HTML parser,
+/// so it only looks at attributes within tags, e.g. `<a href="foo.md">`.
+fn get_html_links(html: &str) -> Vec<Range<usize>> {
+    let bytes = html.as_bytes();
+    let mut links = Vec::new();
+    let mut in_tag = false;
+    let mut idx = 0;
+    while let Some(byte) = bytes.get(idx) {
+        match byte {
+            b'<' if !in_tag => in_tag = true,
+            b'>' if in_tag => in_tag = false,
+            // Skip over quoted values of other attributes.
+            b'"' | b'\'' if in_tag => {
+                idx = skip_while(bytes, idx + 1, |b| b != byte);
+            }
+            b if in_tag && b.is_ascii_whitespace() => {
+                let name_start = skip_while(bytes, idx, u8::is_ascii_whitespace);
+                let name_end = skip_while(bytes, name_start, |b| {
+                    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b':')
+                });
+                let name = &html[name_start..name_end];
+                let value_start = skip_while(bytes, name_end, u8::is_ascii_whitespace);
+                idx = name_end;
+                if !HTML_LINK_ATTRIBUTES
+                    .iter()
+                    .any(|attr| attr.eq_ignore_ascii_case(name))
+                    || bytes.get(value_start) != Some(&b'=')
+                {
+                    continue;
+                }
+                let value_start = skip_while(bytes, value_start + 1, u8::is_ascii_whitespace);
+                let range = match bytes.get(value_start) {
+                    Some(quote @ (b'"' | b'\'')) => {
+                        let end = skip_while(bytes, value_start + 1, |b| b != quote);
+                        // Resume after the closing quote.
+                        idx = end + 1;
+                        (value_start + 1)..end
+                    }
+                    _ => {
+                        let end = skip_while(bytes, value_start, |b| {
+                            !b.is_ascii_whitespace() && *b != b'>'
+                        });
+                        idx = end;
+                        value_start..end
+                    }
+                };
+                if !range.is_empty() {
+                    links.push(range);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        idx += 1;
+    }
+    links
+}
+
+/// A footnote definition with a single word body, e.g. `[^1]: foo`,
+/// is parsed as a link reference definition, but its body isn't a link.
+fn is_footnote_definition(input: &str, destination: Node) -> Result<bool, LinkError> {
+    let is_definition = destination
+        .parent()
+        .is_some_and(|parent| parent.kind() == "link_reference_definition");
+    match destination.prev_named_sibling() {
+        Some(label) if is_definition => Ok(node_text(input, label)?.starts_with("[^")),
+        _ => Ok(false),
+    }
+}
+
+/// Shrinks the range of `slice` to exclude leading and trailing whitespace,
+/// including Unicode whitespace, e.g. a no-break space pasted into a link.
+fn trim_range(slice: &str, range: Range<usize>) -> Range<usize> {
+    let start = range.start + (slice.len() - slice.trim_start().len());
+    let end = range.end - (slice.len() - slice.trim_end().len());
+    start..end.max(start)
+}
+
+/// Shrinks the range of a destination wrapped in angle brackets,
+/// e.g. `<foo bar.md>`, to exclude them.
+fn strip_angle_brackets(input: &str, range: Range<usize>) -> Range<usize> {
+    let destination = &input[range.clone()];
+    if destination.len() >= 2 && destination.starts_with('<') && destination.ends_with('>') {
+        (range.start + 1)..(range.end - 1)
+    } else {
+        range
+    }
+}
+
+/// A reference link, e.g. `[foo]`, `[foo][]` or `[text][foo]`,
+/// tied to the definition it refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkReference {
+    /// The byte range of the whole reference link.
+    pub range: Range<usize>,
+    /// The byte range of the link's text, excluding the brackets.
+    pub text: Range<usize>,
+    /// The normalized label of the reference.
+    pub label: String,
+    /// The byte range of the matching definition's destination.
+    pub destination: Range<usize>,
+}
+
+/// Returns every shortcut, collapsed and full reference link
+/// which has a matching definition in the input markdown, in document order.
+/// Bracketed text without a matching definition isn't a link,
+/// so isn't returned.
+pub fn get_link_references(input: &str) -> Vec<LinkReference> {
+    let tree = crate::parse_tree(input);
+    let mut query_cur = QueryCursor::new();
+    let inline_query = &*REFERENCE_INLINE_QUERY;
+
+    // If a label is defined more than once,
+    // the first definition takes precedence.
+    let mut definitions = HashMap::new();
+    for definition in definitions_in_tree(&tree, input) {
+        definitions
+            .entry(definition.label)
+            .or_insert(definition.destination);
+    }
+
+    let reference_idx = inline_query.capture_index_for_name("reference").unwrap();
+    let label_idx = inline_query.capture_index_for_name("label").unwrap();
+    let text_idx = inline_query.capture_index_for_name("text").unwrap();
+    let mut references = Vec::new();
+    for inline_tree in tree.inline_trees() {
+        let matches = query_cur.matches(inline_query, inline_tree.root_node(), input.as_bytes());
+        for reference in matches {
+            let node = |idx| {
+                reference
+                    .captures
+                    .iter()
+                    .find(|capture| capture.index == idx)
+                    .map(|capture| capture.node)
+            };
+            let (Some(reference), Some(label), Some(text)) =
+                (node(reference_idx), node(label_idx), node(text_idx))
+            else {
+                continue;
+            };
+            let label = normalize_label(&input[label.byte_range()]);
+            if let Some(destination) = definitions.get(&label) {
+                references.push(LinkReference {
+                    range: reference.byte_range(),
+                    text: text.byte_range(),
+                    label,
+                    destination: destination.clone(),
+                });
+            }
+        }
+    }
+    references.sort_by_key(|reference| reference.range.start);
+    references
+}
+
+/// A link reference definition, e.g. `[foo]: foo.md "Foo"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkDefinition {
+    /// The byte range of the whole definition.
+    pub range: Range<usize>,
+    /// The normalized label of the definition.
+    pub label: String,
+    /// The byte range of the definition's destination.
+    pub destination: Range<usize>,
+}
+
+/// Returns every link reference definition in the input markdown,
+/// in document order, including any which redefine an earlier label.
+/// Footnote definitions, e.g. `[^1]: Note.`, aren't links,
+/// so aren't returned.
+pub fn get_link_definitions(input: &str) -> Vec<LinkDefinition> {
+    definitions_in_tree(&crate::parse_tree(input), input)
+}
+
+fn definitions_in_tree(tree: &MarkdownTree, input: &str) -> Vec<LinkDefinition> {
+    let mut query_cur = QueryCursor::new();
+    let matches = query_cur.matches(
+        &DEFINITION_QUERY,
+        tree.block_tree().root_node(),
+        input.as_bytes(),
+    );
+    let mut definitions = Vec::new();
+    for definition in matches {
+        let capture = |name| {
+            let idx = DEFINITION_QUERY.capture_index_for_name(name).unwrap();
+            definition
+                .captures
+                .iter()
+                .find(|capture| capture.index == idx)
+                .map(|capture| capture.node.byte_range())
+        };
+        let (Some(range), Some(label), Some(destination)) = (
+            capture("definition"),
+            capture("label"),
+            capture("destination"),
+        ) else {
+            continue;
+        };
+        let label = normalize_label(&input[label]);
+        if label.starts_with('^') {
+            continue;
+        }
+        definitions.push(LinkDefinition {
+            range,
+            label,
+            destination,
+        });
+    }
+    definitions.sort_by_key(|definition| definition.range.start);
+    definitions
+}
+
+/// Labels are matched case insensitively, ignoring the surrounding brackets
+/// and treating consecutive whitespace as a single space.
+fn normalize_label(label: &str) -> String {
+    let label = label.trim_start_matches('[').trim_end_matches(']');
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Rewrites every link into the canonical inline form, e.g. `[text](url)`.
+///
+/// Reference links are expanded into inline links,
+/// and the definitions they used are removed.
+/// Definitions which no link used are kept.
+/// A blank line left on its own by the removed definitions is removed too,
+/// so no more than one blank line separates the blocks around them.
+/// Whitespace surrounding the destinations and titles of inline links
+/// and images is removed. Autolinks are left as they are.
+pub fn canonicalize_links(input: &str) -> Cow<'_, str> {
+    let tree = crate::parse_tree(input);
+    let mut query_cur = QueryCursor::new();
+    // Each edit replaces a range of the input.
+    let mut edits = Vec::new();
+    let inline_form = |destination: &str, title: Option<&str>| match title {
+        Some(title) => format!("({destination} {title})"),
+        None => format!("({destination})"),
+    };
+
+    // The definitions' titles and ranges, keyed by destination,
+    // which is unique to each definition.
+    let mut definitions = HashMap::new();
+    let matches = query_cur.matches(
+        &DEFINITION_QUERY,
+        tree.block_tree().root_node(),
+        input.as_bytes(),
+    );
+    for definition in matches {
+        let capture = |name| {
+            let idx = DEFINITION_QUERY.capture_index_for_name(name).unwrap();
+            definition
+                .captures
+                .iter()
+                .find(|capture| capture.index == idx)
+                .map(|capture| capture.node.byte_range())
+        };
+        if let (Some(range), Some(destination)) = (capture("definition"), capture("destination")) {
+            definitions.insert(
+                destination.start,
+                (capture("title").map(|title| &input[title]), range),
+            );
+        }
+    }
+    let mut used = Vec::new();
+    for reference in get_link_references(input) {
+        let Some((title, definition)) = definitions.get(&reference.destination.start) else {
+            continue;
+        };
+        // Everything after the text, e.g. `][label]`, becomes the destination.
+        let destination = &input[reference.destination.clone()];
+        edits.push((
+            (reference.text.end + 1)..reference.range.end,
+            inline_form(destination, *title),
+        ));
+        used.push(definition.clone());
+    }
+    used.sort_by_key(|range| range.start);
+    used.dedup();
+    // Runs of adjacent definitions, each including its line ending.
+    let mut removed: Vec<Range<usize>> = Vec::new();
+    for definition in used {
+        let mut end = definition.end;
+        if !input[..end].ends_with('\n') {
+            end += input[end..]
+                .find('\n')
+                .map_or(input.len() - end, |idx| idx + 1);
+        }
+        match removed.last_mut() {
+            Some(run) if run.end == definition.start => run.end = end,
+            _ => removed.push(definition.start..end),
+        }
+    }
+    for mut run in removed {
+        let blank_before = blank_line_before(input, run.start);
+        let blank_after = blank_line_after(input, run.end);
+        match (blank_before, blank_after) {
+            // Between two blank lines, or at the start, the one after goes.
+            (Some(_), Some(after)) => run.end = after.end,
+            (None, Some(after)) if run.start == 0 => run.end = after.end,
+            // At the end, the one before goes.
+            (Some(before), None) if input[run.end..].trim().is_empty() => run.start = before.start,
+            _ => {}
+        }
+        edits.push((run, String::new()));
+    }
+
+    for inline_tree in tree.inline_trees() {
+        let matches = query_cur.matches(
+            &INLINE_LINK_QUERY,
+            inline_tree.root_node(),
+            input.as_bytes(),
+        );
+        for link in matches {
+            let node = |name| {
+                let idx = INLINE_LINK_QUERY.capture_index_for_name(name).unwrap();
+                link.captures
+                    .iter()
+                    .find(|capture| capture.index == idx)
+                    .map(|capture| capture.node)
+            };
+            let Some(link) = node("link") else {
+                continue;
+            };
+            // An image's text is preceded by `![` rather than `[`.
+            let text_end = node("text").map_or_else(
+                || link.start_byte() + if link.kind() == "image" { 2 } else { 1 },
+                |text| text.end_byte(),
+            );
+            let destination = node("destination").map_or("", |node| &input[node.byte_range()]);
+            let title = node("title").map(|node| &input[node.byte_range()]);
+            let range = (text_end + 1)..link.end_byte();
+            let canonical = inline_form(destination, title);
+            if input[range.clone()] != canonical {
+                edits.push((range, canonical));
+            }
+        }
+    }
+
+    if edits.is_empty() {
+        return Cow::Borrowed(input);
+    }
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut output = String::with_capacity(input.len());
+    let mut cursor = 0;
+    for (range, replacement) in edits {
+        output += &input[cursor..range.start];
+        output += &replacement;
+        cursor = range.end;
+    }
+    output += &input[cursor..];
+    Cow::Owned(output)
+}
+
+/// The range of the line ending at `idx`, if it's blank.
+fn blank_line_before(input: &str, idx: usize) -> Option<Range<usize>> {
+    let before = input[..idx].strip_suffix('\n')?;
+    let start = before.rfind('\n').map_or(0, |idx| idx + 1);
+    before[start..].trim().is_empty().then_some(start..idx)
+}
+
+/// The range of the line starting at `idx`, if it's blank.
+fn blank_line_after(input: &str, idx: usize) -> Option<Range<usize>> {
+    let end = idx + input[idx..].find('\n')? + 1;
+    input[idx..end].trim().is_empty().then_some(idx..end)
+}
+
+/// Returns the byte range of every link in the input markdown
+/// that is within the given scope.
+/// The returned vector may not be ordered.
+pub fn get_links_in_scope(input: &str, scope: LinkScope) -> Vec<Range<usize>> {
+    let mut links = get_links(input);
+    links.retain(|link| scope.includes(&input[link.clone()]));
+    links
+}
+
+/// An error returned by a link replacement callback.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The ways replacing links can fail.
+#[derive(Debug, Error)]
+pub enum LinkError {
+    /// A replacement link can't be a link destination,
+    /// because it contains a line break.
+    #[error("'{link}' can't be a link destination as it contains a line break")]
+    MalformedLink { link: String },
+    /// The replacement callback returned an error.
+    #[error(transparent)]
+    Replacement(BoxError),
+    /// A node of the syntax tree doesn't fit the content,
+    /// because the tree was parsed from different content.
+    #[error("{kind} node at {range:?} doesn't fit the content, which is {len} bytes long")]
+    OutOfBounds {
+        kind: &'static str,
+        range: Range<usize>,
+        len: usize,
+    },
+    /// Writing the new content failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Will error if `replacement` returns an error
+/// or a link which can't be a link destination.
+///
+/// Links don't overlap in valid markdown, but if one link's range were to
+/// lie within another's, only the outer link would be replaced,
+/// so the two replacements can't corrupt each other.
+/// [`replace_links_with_text`] returns the links skipped this way.
 pub fn replace_links(
     content: &str,
-    replacement: impl Fn(&str) -> Result<Option<String>>,
-) -> Result<Cow<'_, str>> {
-    let mut state: Option<(String, usize)> = None;
-    let mut links = get_links(content);
-    links.sort_by_key(|range| range.start);
+    replacement: impl Fn(&str) -> Result<Option<String>, BoxError>,
+) -> Result<Cow<'_, str>, LinkError> {
+    replace_links_with_changes(content, replacement).map(|(new_content, _)| new_content)
+}
+
+/// A link rewritten by [`replace_links_with_changes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkChange {
+    /// The byte range of the link in the original content.
+    pub range: Range<usize>,
+    pub old: String,
+    pub new: String,
+}
+
+/// Like [`replace_links`], but also returns every link that `replacement`
+/// returned a new link for, in document order.
+pub fn replace_links_with_changes(
+    content: &str,
+    replacement: impl Fn(&str) -> Result<Option<String>, BoxError>,
+) -> Result<(Cow<'_, str>, Vec<LinkChange>), LinkError> {
+    replace_ranges(content, get_links(content), &mut Vec::new(), |range| {
+        replacement(&content[range])
+    })
+}
+
+/// Like [`replace_links`], but `replacement` is also given the text of
+/// inline links and images, e.g. `foo` in `[foo](foo.md)`.
+/// Other links, including reference links, whose destination is shared
+/// with every reference to the same definition, are given `None`.
+///
+/// Also returns the byte ranges of any links which were skipped
+/// because they lie within another link, so callers can warn about them.
+pub fn replace_links_with_text(
+    content: &str,
+    replacement: impl Fn(&str, Option<&str>) -> Result<Option<String>, BoxError>,
+) -> Result<(Cow<'_, str>, Vec<Range<usize>>), LinkError> {
+    let links = get_links_with_kind(content);
+    let texts: HashMap<_, _> = links
+        .iter()
+        .filter_map(|link| Some((link.range.start, link.text.clone()?)))
+        .collect();
+    let ranges = links.into_iter().map(|link| link.range).collect();
+    let mut skipped = Vec::new();
+    let (new_content, _) = replace_ranges(content, ranges, &mut skipped, |range| {
+        let text = texts.get(&range.start).map(|text| &content[text.clone()]);
+        replacement(&content[range], text)
+    })?;
+    Ok((new_content, skipped))
+}
+
+/// Like [`replace_links`], but `replacement` is given the percent decoded
+/// path of each link, e.g. `my notes/café.md` for
+/// `my%20notes/caf%C3%A9.md#intro`, and the path it returns is percent encoded,
+/// so `C#.md` becomes `C%23.md`.
+/// Any query or fragment is kept as written.
+///
+/// Links without a path, e.g. `#intro`,
+/// and links whose path isn't valid utf8 once decoded are left unchanged.
+pub fn replace_links_decoded(
+    content: &str,
+    replacement: impl Fn(&str) -> Result<Option<String>, BoxError>,
+) -> Result<Cow<'_, str>, LinkError> {
+    replace_links(content, |link| {
+        let (path, suffix) = split_link(link);
+        let Some(decoded) = decode_link_path(path) else {
+            return Ok(None);
+        };
+        if decoded.is_empty() {
+            return Ok(None);
+        }
+        Ok(replacement(&decoded)?.map(|new_path| encode_link_path(&new_path) + suffix))
+    })
+}
+
+/// Like [`replace_links`], but writes the new content to `writer`
+/// as it goes, rather than building it in memory.
+/// Content written before an error is left in the writer.
+pub fn replace_links_to_writer(
+    content: &str,
+    replacement: impl Fn(&str) -> Result<Option<String>, BoxError>,
+    writer: impl io::Write,
+) -> Result<(), LinkError> {
+    write_ranges(
+        content,
+        get_links(content),
+        |range| replacement(&content[range]),
+        writer,
+    )
+}
+
+/// Like [`replace_ranges`], but writes the new content to `writer`.
+/// Overlapping ranges are skipped in the same way.
+fn write_ranges(
+    content: &str,
+    links: Vec<Range<usize>>,
+    replacement: impl Fn(Range<usize>) -> Result<Option<String>, BoxError>,
+    mut writer: impl io::Write,
+) -> Result<(), LinkError> {
+    let mut cursor = 0;
+    for link in outermost_ranges(links, &mut Vec::new()) {
+        let Some(new_link) = replacement(link.clone()).map_err(LinkError::Replacement)? else {
+            continue;
+        };
+        check_link(&new_link)?;
+        writer.write_all(&content.as_bytes()[cursor..link.start])?;
+        writer.write_all(new_link.as_bytes())?;
+        cursor = link.end;
+    }
+    writer.write_all(&content.as_bytes()[cursor..])?;
+    Ok(())
+}
+
+/// Every link in a document, grouped by kind.
+/// Each group is in document order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AllLinks {
+    /// The destinations of inline links, e.g. `foo.md` in `[foo](foo.md)`.
+    pub inline: Vec<Range<usize>>,
+    /// The destinations of images, e.g. `foo.png` in `![foo](foo.png)`.
+    pub image: Vec<Range<usize>>,
+    /// Reference links, e.g. `[foo]` or `[text][foo]`, which have
+    /// no destination of their own, so the range covers the whole link.
+    pub reference: Vec<Range<usize>>,
+    /// The destinations of link reference definitions,
+    /// e.g. `foo.md` in `[foo]: foo.md`.
+    pub definition: Vec<Range<usize>>,
+    /// Autolinks, e.g. `https://hugom.uk` in `<https://hugom.uk>`.
+    pub autolink: Vec<Range<usize>>,
+    /// The `href` and `src` attributes of raw HTML.
+    pub html: Vec<Range<usize>>,
+}
+
+/// Returns every link in the input markdown, grouped by kind.
+pub fn extract_all(input: &str) -> AllLinks {
+    let mut all = AllLinks {
+        reference: get_link_references(input)
+            .into_iter()
+            .map(|reference| reference.range)
+            .collect(),
+        ..Default::default()
+    };
+    let mut links = get_links_with_kind(input);
+    links.sort_by_key(|link| link.range.start);
     for link in links {
-        let link_str = content[link.clone()].trim();
-        if let Some(new_link) = replacement(link_str)? {
+        let group = match link.kind {
+            LinkKind::Inline => &mut all.inline,
+            LinkKind::Image => &mut all.image,
+            LinkKind::Definition => &mut all.definition,
+            LinkKind::Autolink => &mut all.autolink,
+            LinkKind::Html => &mut all.html,
+        };
+        group.push(link.range);
+    }
+    all
+}
+
+/// Errors if the link can't be a link destination.
+fn check_link(link: &str) -> Result<(), LinkError> {
+    if link.contains(['\n', '\r']) {
+        return Err(LinkError::MalformedLink {
+            link: link.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Like [`replace_links`], but `replacement` is only called for local links
+/// to files or directories which exist, along with the path they resolve to.
+///
+/// Relative links are resolved against `dir`,
+/// usually the directory of the file containing them,
+/// and absolute links, e.g. `/notes/foo.md`, against `root`.
+/// External links, links to missing paths and links with only a fragment
+/// or query, e.g. `#foo`, are left as they are.
+pub fn replace_existing_local_links<'a>(
+    content: &'a str,
+    dir: &Path,
+    root: &Path,
+    replacement: impl Fn(&str, &Path) -> Result<Option<String>, BoxError>,
+) -> Result<Cow<'a, str>, LinkError> {
+    replace_links(content, |link| {
+        if !is_local_link(link) {
+            return Ok(None);
+        }
+        match resolve_link(link, dir, root) {
+            Some((path, _)) if path.exists() => replacement(link, &path),
+            _ => Ok(None),
+        }
+    })
+}
+
+/// Replaces the given ranges of the content with what `replacement`
+/// returns for them, returning every replacement made in document order.
+/// Ranges which overlap an earlier range, e.g. one nested inside another,
+/// are added to `skipped` instead, so the outermost range wins.
+pub(crate) fn replace_ranges<'a>(
+    content: &'a str,
+    links: Vec<Range<usize>>,
+    skipped: &mut Vec<Range<usize>>,
+    replacement: impl Fn(Range<usize>) -> Result<Option<String>, BoxError>,
+) -> Result<(Cow<'a, str>, Vec<LinkChange>), LinkError> {
+    let mut state: Option<(String, usize)> = None;
+    let mut changes = Vec::new();
+    for link in outermost_ranges(links, skipped) {
+        let link_str = &content[link.clone()];
+        if let Some(new_link) = replacement(link.clone()).map_err(LinkError::Replacement)? {
+            check_link(&new_link)?;
             let (new_content, cursor) = state.take().unwrap_or((String::new(), 0));
             state = Some((
                 new_content + &content[cursor..link.start] + &new_link,
                 link.end,
             ));
+            changes.push(LinkChange {
+                range: link,
+                old: link_str.to_string(),
+                new: new_link,
+            });
         }
     }
     if let Some((mut new_content, idx)) = state {
         new_content += &content[idx..];
-        Ok(Cow::Owned(new_content))
+        Ok((Cow::Owned(new_content), changes))
     } else {
-        Ok(Cow::Borrowed(content))
+        Ok((Cow::Borrowed(content), changes))
     }
 }
 
+/// Sorts the ranges, leaving out any which overlap an earlier range,
+/// which are added to `skipped` instead.
+fn outermost_ranges(
+    mut ranges: Vec<Range<usize>>,
+    skipped: &mut Vec<Range<usize>>,
+) -> Vec<Range<usize>> {
+    // Outer ranges come before the ranges nested inside them.
+    ranges.sort_by_key(|range| (range.start, std::cmp::Reverse(range.end)));
+    let mut covered = 0;
+    ranges.retain(|range| {
+        if range.start < covered {
+            skipped.push(range.clone());
+            return false;
+        }
+        covered = range.end;
+        true
+    });
+    ranges
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use anyhow::Result;
+    use std::cell::RefCell;
     use std::error::Error;
 
     #[test]
@@ -94,4 +1052,601 @@ mod test {
         assert_eq!(actual, expected);
         Ok(())
     }
+
+    #[test]
+    fn existing_local_links() -> Result<()> {
+        let tmp = tempfile::TempDir::new()?;
+        let root = tmp.path();
+        std::fs::create_dir(root.join("notes"))?;
+        std::fs::write(root.join("notes/a b.md"), "# A\n")?;
+        std::fs::write(root.join("c.md"), "# C\n")?;
+
+        let input = "\
+[a](a%20b.md#top) [missing](missing.md) [web](https://hugom.uk)
+[c](/c.md) [self](#self) [dir](../notes/)
+";
+        let seen = std::cell::RefCell::new(Vec::new());
+        let actual =
+            replace_existing_local_links(input, &root.join("notes"), root, |link, path| {
+                seen.borrow_mut()
+                    .push(path.strip_prefix(root)?.to_path_buf());
+                Ok(Some(format!("{link}?seen")))
+            })?;
+        assert_eq!(
+            actual,
+            "\
+[a](a%20b.md#top?seen) [missing](missing.md) [web](https://hugom.uk)
+[c](/c.md?seen) [self](#self) [dir](../notes/?seen)
+"
+        );
+        assert_eq!(
+            seen.into_inner(),
+            [
+                Path::new("notes/a b.md"),
+                Path::new("c.md"),
+                Path::new("notes")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn replace_decoded() -> Result<(), LinkError> {
+        let content = "\
+[a](my%20notes/caf%C3%A9.md#intro) [b](<b c.md>) [d](100%25.md?x=1)
+[e](#intro) [f](f.md)
+";
+        let seen = RefCell::new(Vec::new());
+        let actual = replace_links_decoded(content, |path| {
+            seen.borrow_mut().push(path.to_string());
+            Ok(match path {
+                "f.md" => Some("C#/f?.md".to_string()),
+                _ => Some(format!("new dir/{path}")),
+            })
+        })?;
+        assert_eq!(
+            seen.into_inner(),
+            ["my notes/café.md", "b c.md", "100%.md", "f.md"]
+        );
+        assert_eq!(
+            actual,
+            "\
+[a](new%20dir/my%20notes/café.md#intro) [b](<new%20dir/b%20c.md>) [d](new%20dir/100%25.md?x=1)
+[e](#intro) [f](C%23/f%3F.md)
+"
+        );
+        // The encoded paths decode back to the returned ones.
+        let mut paths: Vec<_> = get_links(&actual)
+            .into_iter()
+            .map(|range| &actual[range])
+            .filter(|link| !link.starts_with('#'))
+            .map(|link| {
+                let (path, _) = split_link(link);
+                decode_link_path(path).unwrap().into_owned()
+            })
+            .collect();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                "C#/f?.md",
+                "new dir/100%.md",
+                "new dir/b c.md",
+                "new dir/my notes/café.md"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn replace_to_writer() -> Result<()> {
+        let input = "\
+[a](a.md) <https://hugom.uk> ![b](b.png \"B\")
+
+[c]: c.md
+
+[[inner](inner.md)](outer.md) [<a href=\"inner.md\">inner</a>](outer.md)
+";
+        let replacement = |link: &str| Ok(link.ends_with(".md").then(|| format!("notes/{link}")));
+        let mut output = Vec::new();
+        replace_links_to_writer(input, replacement, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            replace_links(input, replacement)?
+        );
+
+        // Nested ranges are skipped like `replace_ranges` skips them.
+        let content = "0123456789";
+        let ranges = vec![2..4, 1..5, 6..8, 7..9, 1..2];
+        let replacement = |range: Range<usize>| Ok(Some(format!("[{}]", &content[range])));
+        let mut output = Vec::new();
+        write_ranges(content, ranges.clone(), replacement, &mut output)?;
+        let (expected, _) = replace_ranges(content, ranges, &mut Vec::new(), replacement)?;
+        assert_eq!(String::from_utf8(output)?, expected);
+
+        let mut output = Vec::new();
+        replace_links_to_writer(input, |_| Ok(None), &mut output)?;
+        assert_eq!(String::from_utf8(output)?, input);
+
+        let err = replace_links_to_writer(input, |_| Ok(Some("x\n".into())), Vec::new());
+        assert!(matches!(err, Err(LinkError::MalformedLink { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn link_errors() {
+        let input = "[a](a.md)\n";
+        let err = replace_links(input, |_| Err("no replacement".into())).unwrap_err();
+        assert!(
+            matches!(&err, LinkError::Replacement(source) if source.to_string() == "no replacement")
+        );
+
+        let err = replace_links(input, |_| Ok(Some("b\n.md".to_string()))).unwrap_err();
+        assert!(matches!(err, LinkError::MalformedLink { link } if link == "b\n.md"));
+    }
+
+    #[test]
+    fn canonical_links() {
+        let input = "\
+Text [Foo], [foo][] and [text][Bar] with [a](
+  a.md
+  \"A\" ), ![i]( i.png ) and <https://hugom.uk>.
+
+[foo]: ./foo.md \"Foo\"
+[unused]: unused.md
+[bar]:
+  <./b ar.md>
+";
+        let expected = "\
+Text [Foo](./foo.md \"Foo\"), [foo](./foo.md \"Foo\") and [text](<./b ar.md>) with [a](a.md \"A\"), ![i](i.png) and <https://hugom.uk>.
+
+[unused]: unused.md
+";
+        assert_eq!(canonicalize_links(input), expected);
+        assert!(matches!(canonicalize_links(expected), Cow::Borrowed(_)));
+
+        // The blank lines the removed definitions leave are removed too.
+        let cases = [
+            ("See [a].\n\n[a]: a.md\n", "See [a](a.md).\n"),
+            ("See [a].\n\n[a]: a.md", "See [a](a.md).\n"),
+            ("[a]: a.md\n\nSee [a].\n", "See [a](a.md).\n"),
+            (
+                "See [a] [b].\n\n[a]: a.md\n[b]: b.md\n\nMore.\n",
+                "See [a](a.md) [b](b.md).\n\nMore.\n",
+            ),
+            (
+                "See [a].\n\n[a]: a.md\n[c]: c.md\n",
+                "See [a](a.md).\n\n[c]: c.md\n",
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(canonicalize_links(input), expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn mismatched_tree() {
+        let content = "[a](a.md)\n";
+        let tree = crate::parse_tree("Some longer content\n\n[a](a.md)\n");
+        let err = get_links_in_tree(&tree, content).unwrap_err();
+        assert!(matches!(err, LinkError::OutOfBounds { len: 10, .. }));
+        assert!(err
+            .to_string()
+            .ends_with("doesn't fit the content, which is 10 bytes long"));
+    }
+
+    #[test]
+    fn gfm_constructs() {
+        let input = "\
+| link |
+| --- |
+| [a](a.md) |
+
+~~[b](b.md)~~ and ~[c](c.md)~
+
+- [ ] [d](d.md)
+- [x] [e](e.md)
+
+www.example.com isn't a link.
+";
+        // Neither tree-sitter-md nor pulldown-cmark recognise literal autolinks,
+        // e.g. `www.example.com`, which would be external anyway.
+        let links: Vec<_> = get_links(input)
+            .into_iter()
+            .map(|range| &input[range])
+            .collect();
+        assert_eq!(links, ["a.md", "b.md", "c.md", "d.md", "e.md"]);
+    }
+
+    #[test]
+    fn link_kinds() {
+        let input = "[a](a.md) <https://hugom.uk>\n\n[b]: b.md\n";
+        let mut links: Vec<_> = get_links_with_kind(input)
+            .into_iter()
+            .map(|link| (&input[link.range], link.kind))
+            .collect();
+        links.sort_by_key(|(link, _)| *link);
+        assert_eq!(
+            links,
+            [
+                ("a.md", LinkKind::Inline),
+                ("b.md", LinkKind::Definition),
+                ("https://hugom.uk", LinkKind::Autolink),
+            ]
+        );
+    }
+
+    #[test]
+    fn link_texts() -> Result<()> {
+        let input = "\
+[a *b*](a.md) ![image](i.png) [](empty.md) <https://hugom.uk> [ref]
+
+[ref]: ref.md
+";
+        let mut texts: Vec<_> = get_links_with_kind(input)
+            .into_iter()
+            .map(|link| (&input[link.range], link.text.map(|text| &input[text])))
+            .collect();
+        texts.sort();
+        assert_eq!(
+            texts,
+            [
+                ("a.md", Some("a *b*")),
+                ("empty.md", None),
+                ("https://hugom.uk", None),
+                ("i.png", Some("image")),
+                ("ref.md", None),
+            ]
+        );
+
+        let (actual, skipped) = replace_links_with_text(input, |link, text| {
+            Ok(text.map(|text| format!("{}-{link}", text.len())))
+        })?;
+        assert!(skipped.is_empty());
+        assert_eq!(
+            actual,
+            "\
+[a *b*](5-a.md) ![image](5-i.png) [](empty.md) <https://hugom.uk> [ref]
+
+[ref]: ref.md
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn all_links() {
+        let input = "\
+[a](a.md) ![b](b.png) [c] <https://hugom.uk> <img src=\"d.png\">
+
+[c]: c.md
+";
+        let all = extract_all(input);
+        let texts = |ranges: &[Range<usize>]| -> Vec<&str> {
+            ranges.iter().map(|range| &input[range.clone()]).collect()
+        };
+        assert_eq!(texts(&all.inline), ["a.md"]);
+        assert_eq!(texts(&all.image), ["b.png"]);
+        assert_eq!(texts(&all.reference), ["[c]"]);
+        assert_eq!(texts(&all.definition), ["c.md"]);
+        assert_eq!(texts(&all.autolink), ["https://hugom.uk"]);
+        assert_eq!(texts(&all.html), ["d.png"]);
+    }
+
+    #[test]
+    fn link_changes() -> Result<()> {
+        let input = "[a](a.md) [b](b.md) [c](c.md)\n";
+        let replacement = |link: &str| Ok((link != "b.md").then(|| format!("x/{link}")));
+        let (actual, changes) = replace_links_with_changes(input, replacement)?;
+        assert_eq!(actual, "[a](x/a.md) [b](b.md) [c](x/c.md)\n");
+        assert_eq!(
+            changes,
+            [
+                LinkChange {
+                    range: 4..8,
+                    old: "a.md".to_string(),
+                    new: "x/a.md".to_string(),
+                },
+                LinkChange {
+                    range: 24..28,
+                    old: "c.md".to_string(),
+                    new: "x/c.md".to_string(),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn html_links() -> Result<()> {
+        let input = "\
+<a href=\"old.md\">old</a> and [new](new.md) <img src=\"x.png\">
+
+Text <img alt=\"href=no.png\" src='a.png'> and <a
+HREF = b.md>b</a>.
+
+<div>
+  <a title=\"x\" href=\"old.md#top\">top</a>
+</div>
+";
+        let mut links: Vec<_> = get_links_with_kind(input)
+            .into_iter()
+            .filter(|link| link.kind == LinkKind::Html)
+            .map(|link| &input[link.range])
+            .collect();
+        links.sort();
+        assert_eq!(links, ["a.png", "b.md", "old.md", "old.md#top", "x.png"]);
+
+        let replacement = |link: &str| {
+            Ok(link
+                .strip_prefix("old.md")
+                .map(|rest| format!("new.md{rest}")))
+        };
+        let actual = replace_links(input, replacement)?;
+        assert!(actual.starts_with("<a href=\"new.md\">old</a>"));
+        assert!(actual.contains("<a title=\"x\" href=\"new.md#top\">"));
+        Ok(())
+    }
+
+    #[test]
+    fn multiline_destinations() -> Result<()> {
+        let input = "[foo](\n    bar.md\n) text\n\n[bar]:\n    ./foo.md\n    \"title\"\n";
+        let expected = "[foo](\n    baz.md\n) text\n\n[bar]:\n    baz.md\n    \"title\"\n";
+        let links: Vec<_> = get_links(input).into_iter().map(|r| &input[r]).collect();
+        assert!(links.iter().all(|link| link.trim() == *link));
+
+        let actual = replace_links(input, |_| Ok(Some("baz.md".to_string())))?;
+        assert_eq!(actual, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn lenient_links() {
+        let input = "\
+import { Chart } from '../components/chart'
+
+See [the guide](guide.md).
+
+<Chart data={[1, 2]} onClick={() => alert(\"<hi>\")} />
+
+<Callout type=\"note\" href=\"callout.md\">
+  Read [the notes](notes.md) and <a href=\"a.md\">a</a>, ünïcödé.
+</Callout>
+
+Last [link](last.md).
+";
+        let links = |ranges: Vec<Range<usize>>| -> Vec<_> {
+            ranges.into_iter().map(|r| &input[r]).collect()
+        };
+        let mut strict = links(get_links(input));
+        strict.sort();
+        assert_eq!(strict, ["a.md", "callout.md", "guide.md", "last.md"]);
+        assert_eq!(
+            links(get_links_lenient(input)),
+            ["guide.md", "callout.md", "notes.md", "a.md", "last.md"]
+        );
+    }
+
+    #[test]
+    fn unique_destinations() {
+        let input = "\
+[a](https://hugom.uk) [b](b.md)
+[c](https://hugom.uk) <https://hugom.uk>
+
+[d]: b.md
+";
+        assert_eq!(
+            get_unique_link_destinations(input),
+            ["https://hugom.uk", "b.md"]
+        );
+    }
+
+    #[test]
+    fn link_free_content() {
+        for input in [
+            "[a](a.md)",
+            "![a](\n  a.png\n)",
+            "[a]\n\n[a]:\n  a.md",
+            "<https://hugom.uk>",
+            "<a href=a.md>a</a>",
+            "{{#include a.rs}}",
+        ] {
+            assert!(!get_links(input).is_empty() || input.starts_with("{{#"));
+            assert!(may_contain_links(input), "{input:?}");
+        }
+        for input in ["# Title\n\nSome [text] and `code`.\n", "- [ ] task\n"] {
+            assert!(!may_contain_links(input), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn angle_bracket_destinations() -> Result<()> {
+        let input = "\
+[a](<a b.md>) ![b](<b.png> \"title\")
+
+[c]: <url>
+[d]: url \"title\"
+[e]: <url with spaces>
+[f]: <./g h.md> 'title'
+";
+        let links: Vec<_> = get_links(input).into_iter().map(|r| &input[r]).collect();
+        assert_eq!(
+            links,
+            [
+                "url",
+                "url",
+                "url with spaces",
+                "./g h.md",
+                "a b.md",
+                "b.png"
+            ]
+        );
+
+        let actual = replace_links(input, |link| Ok(Some(link.replace(' ', "%20"))))?;
+        assert_eq!(
+            actual,
+            "\
+[a](<a%20b.md>) ![b](<b.png> \"title\")
+
+[c]: <url>
+[d]: url \"title\"
+[e]: <url%20with%20spaces>
+[f]: <./g%20h.md> 'title'
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_ranges() -> Result<()> {
+        let content = "0123456789";
+        let ranges = vec![2..4, 1..5, 6..8, 7..9, 1..2];
+        let mut skipped = Vec::new();
+        let (actual, changes) = replace_ranges(content, ranges, &mut skipped, |range| {
+            Ok(Some(format!("[{}]", &content[range])))
+        })?;
+        assert_eq!(actual, "0[1234]5[67]89");
+        let replaced: Vec<_> = changes.into_iter().map(|change| change.range).collect();
+        assert_eq!(replaced, [1..5, 6..8]);
+        assert_eq!(skipped, [1..2, 2..4, 7..9]);
+        Ok(())
+    }
+
+    #[test]
+    fn unicode_whitespace() -> Result<()> {
+        let input = "[a](\u{a0}a.md\u{2003}) text\n\n[b]: \u{a0}b.md\n";
+        let (actual, changes) =
+            replace_links_with_changes(input, |link| Ok(Some(format!("new/{link}"))))?;
+        assert_eq!(
+            actual,
+            "[a](\u{a0}new/a.md\u{2003}) text\n\n[b]: \u{a0}new/b.md\n"
+        );
+        let old: Vec<_> = changes.iter().map(|change| change.old.as_str()).collect();
+        assert_eq!(old, ["a.md", "b.md"]);
+        for change in &changes {
+            assert_eq!(input[change.range.clone()], change.old);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn bom_and_crlf() -> Result<()> {
+        let replacement = |link: &str| Ok(Some(format!("new/{link}")));
+
+        let input = "\u{feff}[a](a.md) text\n\n[b]: b.md\n";
+        let expected = "\u{feff}[a](new/a.md) text\n\n[b]: new/b.md\n";
+        assert_eq!(replace_links(input, replacement)?, expected);
+
+        let input =
+            "# Title\r\n\r\n[a](\r\n  a.md\r\n) <https://hugom.uk>\r\n\r\n[b]:\r\n  b.md\r\n";
+        let expected = "# Title\r\n\r\n[a](\r\n  new/a.md\r\n) <new/https://hugom.uk>\r\n\r\n[b]:\r\n  new/b.md\r\n";
+        assert_eq!(replace_links(input, replacement)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn footnotes() {
+        let input = "\
+Text[^1] and[^note].
+
+[^1]: footnote
+[^note]: See [the note](a/note.md).
+";
+        let links: Vec<_> = get_links(input).into_iter().map(|r| &input[r]).collect();
+        assert_eq!(links, ["a/note.md"]);
+        assert!(get_link_references(input).is_empty());
+    }
+
+    #[test]
+    fn local_links() {
+        for link in [
+            "./a", "../b", "c.md", "/d.md", "#e", "C:\\c", "c:/c", "..\\d",
+        ] {
+            assert!(is_local_link(link), "{link}");
+        }
+        for link in ["mailto:x", "//host", "https://hugom.uk"] {
+            assert!(!is_local_link(link), "{link}");
+            assert!(is_external_link(link), "{link}");
+        }
+    }
+
+    #[test]
+    fn link_scope() {
+        let input = "[a](mailto:me@hugom.uk) [b](//hugom.uk/x) [c](notes/c.md) <https://hugom.uk>";
+        let links = |scope| {
+            let mut links: Vec<_> = get_links_in_scope(input, scope)
+                .into_iter()
+                .map(|range| &input[range])
+                .collect();
+            links.sort();
+            links
+        };
+        assert_eq!(
+            links(LinkScope::All),
+            [
+                "//hugom.uk/x",
+                "https://hugom.uk",
+                "mailto:me@hugom.uk",
+                "notes/c.md"
+            ]
+        );
+        assert_eq!(links(LinkScope::LocalOnly), ["notes/c.md"]);
+        assert_eq!(
+            links(LinkScope::ExternalOnly),
+            ["//hugom.uk/x", "https://hugom.uk", "mailto:me@hugom.uk"]
+        );
+    }
+
+    #[test]
+    fn link_definitions() {
+        let input = "\
+[Foo]
+
+[foo]: ./foo.md
+[^1]: footnote
+[Foo  Bar]: <./bar.md> \"Bar\"
+[foo]: ./ignored.md
+";
+        let definitions: Vec<_> = get_link_definitions(input)
+            .into_iter()
+            .map(|definition| (definition.label, &input[definition.destination]))
+            .collect();
+        assert_eq!(
+            definitions,
+            [
+                ("foo".to_string(), "./foo.md"),
+                ("foo bar".to_string(), "<./bar.md>"),
+                ("foo".to_string(), "./ignored.md"),
+            ]
+        );
+    }
+
+    #[test]
+    fn link_references() {
+        let input = "\
+[Foo] and [foo][] and [text][FOO  bar] but not [missing].
+
+[foo]: ./foo.md
+[foo bar]: <./bar.md>
+[foo]: ./ignored.md
+";
+        let references: Vec<_> = get_link_references(input)
+            .into_iter()
+            .map(|reference| {
+                (
+                    &input[reference.range],
+                    reference.label,
+                    &input[reference.destination],
+                )
+            })
+            .collect();
+        assert_eq!(
+            references,
+            [
+                ("[Foo]", "foo".to_string(), "./foo.md"),
+                ("[foo][]", "foo".to_string(), "./foo.md"),
+                ("[text][FOO  bar]", "foo bar".to_string(), "<./bar.md>"),
+            ]
+        );
+    }
 }
@@ -0,0 +1,859 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use pathdiff::diff_paths;
+use thiserror::Error;
+
+use crate::includes::replace_include_paths;
+use crate::links::{
+    get_link_definitions, get_link_references, is_local_link, may_contain_links, replace_links,
+    LinkError,
+};
+use crate::paths::{
+    canonical_symlink_target, decode_link_path, encode_link_path, is_markdown, normalize_path,
+    resolve_link, resolve_link_path, split_link, SymlinkError, MARKDOWN_EXTENSIONS,
+};
+
+/// The ways planning moves can fail.
+#[derive(Debug, Error)]
+pub enum MoveError {
+    /// Rewriting a file's links failed.
+    #[error(transparent)]
+    Link(#[from] LinkError),
+    /// Following a symlink failed, other than because of a cycle.
+    #[error(transparent)]
+    Symlink(#[from] SymlinkError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// The name of a [`LinkStyle`] wasn't recognised.
+#[derive(Error, Debug)]
+#[error("expected 'relative' or 'root-absolute'")]
+pub struct ParseLinkStyleError;
+
+/// A map from the absolute path of each file or directory being moved
+/// to its absolute path after the move.
+#[derive(Debug, Default, Clone)]
+pub struct MoveList(pub HashMap<PathBuf, PathBuf>);
+impl MoveList {
+    /// Expects the given path to be absolute.
+    /// If both a directory and something inside it are moved,
+    /// the innermost move applies.
+    pub fn get_path_after_move(&self, path: &Path) -> Option<PathBuf> {
+        let (from, to) = self
+            .0
+            .iter()
+            .filter(|(from, _)| path.starts_with(from))
+            .max_by_key(|(from, _)| from.components().count())?;
+        // unwrap safe due to `starts_with` check above
+        let new_path = to.join(path.strip_prefix(from).unwrap());
+        Some(normalize_path(&new_path))
+    }
+
+    /// Whether the given path is, or is inside, the destination of a move.
+    pub fn is_destination(&self, path: &Path) -> bool {
+        self.0.values().any(|to| path.starts_with(to))
+    }
+
+    /// Whether the given path isn't moving but something moves onto it,
+    /// e.g. `b.md` when `a.md` is moved onto it, overwriting it.
+    pub fn is_overwritten(&self, path: &Path) -> bool {
+        self.get_path_after_move(path).is_none()
+            && self.0.iter().any(|(from, to)| match path.strip_prefix(to) {
+                Ok(rel) if rel.as_os_str().is_empty() => true,
+                Ok(rel) => from.join(rel).exists(),
+                Err(_) => false,
+            })
+    }
+}
+impl FromIterator<(PathBuf, PathBuf)> for MoveList {
+    fn from_iter<T: IntoIterator<Item = (PathBuf, PathBuf)>>(iter: T) -> MoveList {
+        let mut list = Self::default();
+        list.0.extend(iter);
+        list
+    }
+}
+
+/// The form of rewritten local links.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LinkStyle {
+    /// Relative links stay relative and absolute links stay absolute.
+    #[default]
+    Relative,
+    /// All links become absolute, i.e. relative to the root.
+    RootAbsolute,
+}
+impl FromStr for LinkStyle {
+    type Err = ParseLinkStyleError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "relative" => Ok(Self::Relative),
+            "root-absolute" => Ok(Self::RootAbsolute),
+            _ => Err(ParseLinkStyleError),
+        }
+    }
+}
+
+/// How links are resolved when applying a [`MoveList`] to a document.
+#[derive(Debug, Clone, Copy)]
+pub struct RewriteContext<'a> {
+    pub moves: &'a MoveList,
+    /// Absolute links, e.g. `/notes/foo.md`, are resolved against the root.
+    pub root: &'a Path,
+    /// Resolve relative links against this directory
+    /// instead of the directory of the file containing them.
+    pub link_base: Option<&'a Path>,
+    /// The root after the moves, if it changes.
+    /// Absolute links are still resolved against the old root,
+    /// but rewritten relative to the new one.
+    pub new_root: Option<&'a Path>,
+    pub link_style: LinkStyle,
+    /// Check every rewritten link resolves to its target after the moves,
+    /// adding a warning if it doesn't.
+    pub verify: bool,
+}
+impl<'a> RewriteContext<'a> {
+    /// A context for the moves under the root, which keeps the style of links,
+    /// resolves relative links against the file containing them
+    /// and doesn't verify the rewritten links.
+    pub fn new(moves: &'a MoveList, root: &'a Path) -> Self {
+        Self {
+            moves,
+            root,
+            link_base: None,
+            new_root: None,
+            link_style: LinkStyle::Relative,
+            verify: false,
+        }
+    }
+}
+
+/// Rewrites the links in the content of `file`,
+/// so that they still point to the same files after the moves.
+/// `file` should be the absolute path of the file before the moves.
+/// The paths of mdbook include directives, e.g. `{{#include file.rs}}`,
+/// are rewritten too.
+///
+/// Links to files that don't exist are left untouched
+/// and a warning is added to `warnings`.
+pub fn rewrite_links_for_moves<'a>(
+    content: &'a str,
+    file: &Path,
+    ctx: &RewriteContext,
+    warnings: &mut Vec<String>,
+) -> Result<Cow<'a, str>, LinkError> {
+    let RewriteContext {
+        moves,
+        root,
+        link_base,
+        new_root,
+        link_style,
+        verify,
+    } = *ctx;
+    let dest_root = new_root.unwrap_or(root);
+    let file_dest = moves
+        .get_path_after_move(file)
+        .unwrap_or_else(|| file.to_path_buf());
+    let file_dir = file.parent().unwrap();
+    let file_dest_dir = file_dest.parent().unwrap();
+    // Relative links are resolved against, and rewritten relative to,
+    // the link base if one was given.
+    let (file_dir, file_dest_dir) = match link_base {
+        Some(base) => (base, base),
+        None => (file_dir, file_dest_dir),
+    };
+
+    // `replace_links` takes a `Fn`, so warnings are collected in a cell.
+    let new_warnings = RefCell::new(Vec::new());
+    // The paths of include directives, unlike links,
+    // aren't percent encoded and have no query or fragment.
+    let replacement = |link: &str, is_include: bool| {
+        // 1. make link absolute based on current file dir or root
+        // 2. if link is to a file in the move list,
+        //    change the link an absolute address of where the file will be
+        //    after the moves
+        // 3. make the link relative to the file containing it after the moves
+        //      *(this may be the same as before the moves)*
+        //      Unless the link was absolute,
+        //      in which case make the link relative to the root
+        if !is_local_link(link) {
+            return None;
+        }
+        // The query and fragment are reattached to the rewritten path.
+        let (link_path, suffix) = match is_include {
+            true => (link, ""),
+            false => split_link(link),
+        };
+        if link_path.is_empty() {
+            return None;
+        }
+        let link_path = if is_include {
+            Cow::Borrowed(link_path)
+        } else if let Some(decoded) = decode_link_path(link_path) {
+            decoded
+        } else {
+            new_warnings.borrow_mut().push(format!(
+                "'{link_path}' in '{}' isn't valid utf8 once decoded",
+                file.display(),
+            ));
+            return None;
+        };
+        // get absolute path to linked file
+        let (mut link_path_abs, was_abs) =
+            resolve_link_path(Path::new(link_path.as_ref()), file_dir, root);
+        if !link_path_abs.exists() {
+            new_warnings.borrow_mut().push(format!(
+                "'{}' in '{}' doesn't exist",
+                link_path_abs.display(),
+                file.display(),
+            ));
+            return None;
+        }
+        // A trailing slash marks a link to a directory,
+        // which site generators resolve to the directory's index.
+        let is_dir_link = link_path.ends_with('/') && link_path_abs.is_dir();
+        if let Some(link_path_post_move) = moves.get_path_after_move(&link_path_abs) {
+            link_path_abs = link_path_post_move
+        } else if file_dest == file && !(was_abs && new_root.is_some()) {
+            // Neither the link's target nor the file containing it are moving,
+            // nor is the root an absolute link is relative to.
+            return None;
+        };
+
+        // mdbook resolves include paths relative to the chapter,
+        // so a leading `/` would make them filesystem absolute.
+        let new_link_path = if !is_include && (was_abs || link_style == LinkStyle::RootAbsolute) {
+            let Ok(path_rel) = link_path_abs.strip_prefix(dest_root) else {
+                new_warnings.borrow_mut().push(format!(
+                    "'{}' in '{}' is outside of the root",
+                    link_path_abs.display(),
+                    file.display(),
+                ));
+                return None;
+            };
+            Path::new("/").join(path_rel)
+        } else {
+            diff_paths(&link_path_abs, file_dest_dir).unwrap()
+        };
+        let mut new_link = match is_include {
+            true => new_link_path.to_string_lossy().into_owned(),
+            false => encode_link_path(&new_link_path.to_string_lossy()),
+        };
+        if new_link.is_empty() {
+            // The link is to the directory of the file containing it.
+            new_link.push('.');
+        }
+        if is_dir_link && !new_link.ends_with('/') {
+            new_link.push('/');
+        }
+        if verify {
+            if let Some(warning) = verify_link(
+                &new_link,
+                file_dest_dir,
+                dest_root,
+                &link_path_abs,
+                &file_dest,
+            ) {
+                new_warnings.borrow_mut().push(warning);
+            }
+        }
+        new_link += suffix;
+        (new_link != link).then_some(new_link)
+    };
+    let new_content = replace_links(content, |link| Ok(replacement(link, false)))?;
+    let with_includes =
+        match replace_include_paths(&new_content, |path| Ok(replacement(path, true)))? {
+            Cow::Owned(with_includes) => Some(with_includes),
+            Cow::Borrowed(_) => None,
+        };
+    warnings.extend(new_warnings.into_inner());
+    Ok(with_includes.map_or(new_content, Cow::Owned))
+}
+
+/// The changes a set of moves makes, as returned by [`plan_moves`].
+#[derive(Debug, Default, Clone)]
+pub struct MovePlan {
+    pub moves: MoveList,
+    /// The new content of every markdown file whose links change,
+    /// keyed by the file's path after the moves.
+    pub rewrites: HashMap<PathBuf, String>,
+    pub warnings: Vec<String>,
+}
+
+/// Which files [`plan_moves`] rewrites the links of.
+#[derive(Debug, Clone, Copy)]
+pub struct ScanOptions<'a> {
+    /// The extensions of the markdown files whose links are rewritten.
+    pub extensions: &'a [&'a str],
+    /// Follow symlinks, rewriting the files they point to, even outside the root.
+    /// Otherwise symlinks are skipped.
+    pub follow_symlinks: bool,
+    /// Only rewrite the links in the files under these directories,
+    /// and in the moved files, whose relative links change with them.
+    /// Links are still resolved against the whole root.
+    /// If empty, the whole root is scanned.
+    pub scan_dirs: &'a [PathBuf],
+}
+impl Default for ScanOptions<'_> {
+    fn default() -> Self {
+        Self {
+            extensions: &MARKDOWN_EXTENSIONS,
+            follow_symlinks: true,
+            scan_dirs: &[],
+        }
+    }
+}
+
+/// Works out the link rewrites needed by the moves in the context,
+/// for the markdown files chosen by the options, without changing anything on disk.
+/// Carrying out the plan, i.e. moving the files and writing the rewrites,
+/// is left to the caller.
+///
+/// Files which a move overwrites are skipped, as they're replaced,
+/// and their rewrites would have the same path as the files replacing them.
+/// Besides the warnings of [`rewrite_links_for_moves`], a rewritten file's
+/// unused or repeated link reference definitions are warned about,
+/// as a move can leave them stale.
+pub fn plan_moves(ctx: &RewriteContext, opts: &ScanOptions) -> Result<MovePlan, MoveError> {
+    let mut plan = MovePlan {
+        moves: ctx.moves.clone(),
+        ..MovePlan::default()
+    };
+    let mut visited = HashSet::new();
+    if opts.scan_dirs.is_empty() {
+        add_dir_to_plan(ctx.root, ctx, opts, &mut visited, &mut plan)?;
+        return Ok(plan);
+    }
+    for dir in opts.scan_dirs {
+        add_dir_to_plan(dir, ctx, opts, &mut visited, &mut plan)?;
+    }
+    for source in ctx.moves.0.keys() {
+        if opts.scan_dirs.iter().any(|dir| source.starts_with(dir)) {
+            continue;
+        }
+        if source.is_dir() {
+            add_dir_to_plan(source, ctx, opts, &mut visited, &mut plan)?;
+        } else {
+            add_file_to_plan(source, ctx, opts, &mut plan)?;
+        }
+    }
+    Ok(plan)
+}
+
+/// `visited` holds the canonical paths of the directories already scanned,
+/// so a symlink to an ancestor isn't followed forever.
+fn add_dir_to_plan(
+    dir: &Path,
+    ctx: &RewriteContext,
+    opts: &ScanOptions,
+    visited: &mut HashSet<PathBuf>,
+    plan: &mut MovePlan,
+) -> Result<(), MoveError> {
+    if !visited.insert(dir.canonicalize()?) {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let mut path = entry?.path();
+        if path.is_symlink() {
+            if !opts.follow_symlinks {
+                continue;
+            }
+            // The moves are keyed by canonical paths.
+            path = match canonical_symlink_target(&path) {
+                Ok(Some(target)) => target,
+                Ok(None) => continue,
+                Err(err @ SymlinkError::Io(_)) => return Err(err.into()),
+                Err(err) => {
+                    plan.warnings.push(format!("skipping {path:?}: {err}"));
+                    continue;
+                }
+            };
+        }
+        if path.is_dir() {
+            add_dir_to_plan(&path, ctx, opts, visited, plan)?;
+        } else if path.is_file() {
+            add_file_to_plan(&path, ctx, opts, plan)?;
+        }
+    }
+    Ok(())
+}
+
+fn add_file_to_plan(
+    file: &Path,
+    ctx: &RewriteContext,
+    opts: &ScanOptions,
+    plan: &mut MovePlan,
+) -> Result<(), MoveError> {
+    if !is_markdown(file, opts.extensions) || ctx.moves.is_overwritten(file) {
+        return Ok(());
+    }
+    let content = fs::read_to_string(file)?;
+    // Most notes have no links, so aren't worth parsing.
+    if !may_contain_links(&content) {
+        return Ok(());
+    }
+    let new_content = rewrite_links_for_moves(&content, file, ctx, &mut plan.warnings)?;
+    if let Cow::Owned(new_content) = new_content {
+        plan.warnings.extend(definition_warnings(&content, file));
+        let file_dest = ctx
+            .moves
+            .get_path_after_move(file)
+            .unwrap_or_else(|| file.to_path_buf());
+        plan.rewrites.insert(file_dest, new_content);
+    }
+    Ok(())
+}
+
+/// Warns about the link reference definitions in the content
+/// which no reference link uses, or which redefine an earlier label,
+/// either of which can be left stale by a move.
+fn definition_warnings(content: &str, file: &Path) -> Vec<String> {
+    let used: HashSet<_> = get_link_references(content)
+        .into_iter()
+        .map(|reference| reference.label)
+        .collect();
+    let mut defined = HashSet::new();
+    let mut warnings = Vec::new();
+    for definition in get_link_definitions(content) {
+        let label = &definition.label;
+        if !defined.insert(label.clone()) {
+            warnings.push(format!(
+                "'[{label}]' in '{}' is defined more than once, only the first definition is used",
+                file.display(),
+            ));
+        } else if !used.contains(label) {
+            warnings.push(format!(
+                "'[{label}]' in '{}' is defined but never used",
+                file.display(),
+            ));
+        }
+    }
+    warnings
+}
+
+/// Returns a warning if the encoded path of a rewritten link,
+/// resolved from `dir`, doesn't lead to `target`.
+fn verify_link(
+    new_link_path: &str,
+    dir: &Path,
+    root: &Path,
+    target: &Path,
+    file: &Path,
+) -> Option<String> {
+    let (resolved, _) = resolve_link(new_link_path, dir, root)?;
+    (resolved != target).then(|| {
+        format!(
+            "rewritten link '{new_link_path}' in '{}' leads to '{}' instead of '{}'",
+            file.display(),
+            resolved.display(),
+            target.display(),
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::Result;
+    use std::fs;
+    use tempfile::TempDir;
+
+    /// Creates `a/note.md` and `b/` under a temporary root,
+    /// and a move list moving `a/note.md` into `b/`.
+    fn setup() -> Result<(TempDir, PathBuf, MoveList)> {
+        let tmp = TempDir::new()?;
+        let root = tmp.path().canonicalize()?;
+        fs::create_dir_all(root.join("a"))?;
+        fs::create_dir_all(root.join("b"))?;
+        fs::write(root.join("a/note.md"), "# Note\n")?;
+        let moves = MoveList::from_iter([(root.join("a/note.md"), root.join("b/note.md"))]);
+        Ok((tmp, root, moves))
+    }
+
+    /// A context which verifies the rewritten links.
+    fn context<'a>(moves: &'a MoveList, root: &'a Path) -> RewriteContext<'a> {
+        RewriteContext {
+            verify: true,
+            ..RewriteContext::new(moves, root)
+        }
+    }
+
+    /// Rewrites the content of `file`, a path relative to the root,
+    /// returning the new content and the warnings.
+    fn rewrite(ctx: &RewriteContext, content: &str, file: &str) -> Result<(String, Vec<String>)> {
+        let mut warnings = Vec::new();
+        let new_content =
+            rewrite_links_for_moves(content, &ctx.root.join(file), ctx, &mut warnings)?;
+        Ok((new_content.into_owned(), warnings))
+    }
+
+    #[test]
+    fn relative_links() -> Result<()> {
+        let (_tmp, root, moves) = setup()?;
+        let content = "[note](a/note.md) [missing](a/missing.md)\n";
+        let (actual, warnings) = rewrite(&context(&moves, &root), content, "index.md")?;
+        assert_eq!(actual, "[note](b/note.md) [missing](a/missing.md)\n");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("missing.md"));
+        Ok(())
+    }
+
+    #[test]
+    fn absolute_links() -> Result<()> {
+        let (_tmp, root, moves) = setup()?;
+        let content = "[note](/a/note.md)\n";
+        let (actual, warnings) = rewrite(&context(&moves, &root), content, "a/other.md")?;
+        assert_eq!(actual, "[note](/b/note.md)\n");
+        assert!(warnings.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn directory_links() -> Result<()> {
+        let (_tmp, root, moves) = setup()?;
+        let content = "[a](./) [b](../b/) [b](../b) [root](/a/)\n";
+        let (actual, warnings) = rewrite(&context(&moves, &root), content, "a/note.md")?;
+        assert_eq!(actual, "[a](../a/) [b](./) [b](.) [root](/a/)\n");
+        assert!(warnings.is_empty(), "{warnings:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn angle_bracket_links() -> Result<()> {
+        let (_tmp, root, _) = setup()?;
+        fs::write(root.join("a/my note.md"), "# Note\n")?;
+        let moves = MoveList::from_iter([(root.join("a/my note.md"), root.join("b/my note.md"))]);
+        let content = "[a]: <a/my note.md> \"Note\"\n[b](<a/my note.md#top>)\n";
+        let (actual, warnings) = rewrite(&context(&moves, &root), content, "index.md")?;
+        assert_eq!(
+            actual,
+            "[a]: <b/my%20note.md> \"Note\"\n[b](<b/my%20note.md#top>)\n"
+        );
+        assert!(warnings.is_empty(), "{warnings:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn planned_moves() -> Result<()> {
+        let (_tmp, root, moves) = setup()?;
+        fs::write(root.join("index.md"), "[note](a/note.md)\n")?;
+        fs::write(
+            root.join("a/other.md"),
+            "[note](note.md) [index](../index.md)\n",
+        )?;
+        fs::write(root.join("a/note.md"), "[other](other.md)\n")?;
+        fs::write(root.join("b/plain.md"), "# Plain\n")?;
+
+        let plan = plan_moves(&context(&moves, &root), &ScanOptions::default())?;
+        let mut rewrites: Vec<_> = plan
+            .rewrites
+            .iter()
+            .map(|(path, content)| (path.strip_prefix(&root).unwrap(), content.as_str()))
+            .collect();
+        rewrites.sort();
+        assert_eq!(
+            rewrites,
+            [
+                (
+                    Path::new("a/other.md"),
+                    "[note](../b/note.md) [index](../index.md)\n"
+                ),
+                (Path::new("b/note.md"), "[other](../a/other.md)\n"),
+                (Path::new("index.md"), "[note](b/note.md)\n"),
+            ]
+        );
+        assert_eq!(plan.moves.0, moves.0);
+        assert!(plan.warnings.is_empty(), "{:?}", plan.warnings);
+        // Nothing was changed on disk.
+        assert!(root.join("a/note.md").is_file());
+        assert!(!root.join("b/note.md").exists());
+        assert_eq!(
+            fs::read_to_string(root.join("index.md"))?,
+            "[note](a/note.md)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn new_root() -> Result<()> {
+        let (_tmp, root, _) = setup()?;
+        let site = root.join("site");
+        fs::create_dir_all(&site)?;
+        fs::write(site.join("logo.png"), "")?;
+        let moves = MoveList::from_iter([(root.join("a"), site.join("a"))]);
+        let ctx = RewriteContext {
+            new_root: Some(&site),
+            ..context(&moves, &root)
+        };
+        let content =
+            "[note](/a/note.md) [logo](/site/logo.png) [b](/b/) [rel](../site/logo.png)\n";
+        let (actual, warnings) = rewrite(&ctx, content, "a/other.md")?;
+        assert_eq!(
+            actual,
+            "[note](/a/note.md) [logo](/logo.png) [b](/b/) [rel](../logo.png)\n"
+        );
+        // `b` is outside the new root, so its link can't be rewritten.
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("outside of the root"));
+
+        // Absolute links in files which aren't moving are rewritten too.
+        let content = "[logo](/site/logo.png) [note](../a/note.md)\n";
+        let (actual, warnings) = rewrite(&ctx, content, "site/index.md")?;
+        assert_eq!(actual, "[logo](/logo.png) [note](a/note.md)\n");
+        assert!(warnings.is_empty(), "{warnings:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn shared_prefixes() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let root = tmp.path().canonicalize()?;
+        let docs = root.join("docs");
+        for dir in ["a", "ab", "b", "shared"] {
+            fs::create_dir_all(docs.join(dir))?;
+        }
+        for file in [
+            "a/note.md",
+            "a/x.md",
+            "a/y.md",
+            "ab/z.md",
+            "b/w.md",
+            "shared/s.md",
+        ] {
+            fs::write(docs.join(file), "")?;
+        }
+        // A directory and a file within it both move,
+        // and `ab` shares a prefix with `a` but isn't inside it.
+        let moves = MoveList::from_iter([
+            (docs.join("a"), docs.join("b/a")),
+            (docs.join("a/x.md"), docs.join("ab/x.md")),
+            (docs.join("ab/z.md"), docs.join("a/z.md")),
+            (docs.join("b/w.md"), docs.join("w.md")),
+        ]);
+        let ctx = context(&moves, &docs);
+        let rewrite = |content, file| -> Result<String> {
+            let (new_content, warnings) = rewrite(&ctx, content, file)?;
+            assert!(warnings.is_empty(), "{warnings:?}");
+            Ok(new_content)
+        };
+
+        assert_eq!(
+            rewrite(
+                "[x](x.md) [y](y.md) [z](../ab/z.md) [w](../b/w.md) [s](../shared/s.md)",
+                "a/note.md"
+            )?,
+            "[x](../../ab/x.md) [y](y.md) [z](../../a/z.md) [w](../../w.md) [s](../../shared/s.md)"
+        );
+        assert_eq!(
+            rewrite(
+                "[note](../a/note.md) [y](../a/y.md) [w](../b/w.md)",
+                "ab/z.md"
+            )?,
+            "[note](../b/a/note.md) [y](../b/a/y.md) [w](../w.md)"
+        );
+        assert_eq!(
+            rewrite("[note](../a/note.md) [z](../ab/z.md)", "a/x.md")?,
+            "[note](../b/a/note.md) [z](../a/z.md)"
+        );
+        assert_eq!(
+            rewrite("[note](../a/note.md) [x](../a/x.md)", "b/w.md")?,
+            "[note](b/a/note.md) [x](ab/x.md)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn include_directives() -> Result<()> {
+        let (_tmp, root, moves) = setup()?;
+        fs::write(root.join("a/code.rs"), "fn main() {}\n")?;
+        let ctx = context(&moves, &root);
+        let content = "\
+{{#include code.rs}}
+{{#rustdoc_include code.rs:10:20}}
+{{#playground ./code.rs editable}}
+[code](code.rs)
+";
+        let (actual, warnings) = rewrite(&ctx, content, "a/note.md")?;
+        assert_eq!(
+            actual,
+            "\
+{{#include ../a/code.rs}}
+{{#rustdoc_include ../a/code.rs:10:20}}
+{{#playground ../a/code.rs editable}}
+[code](../a/code.rs)
+"
+        );
+        assert!(warnings.is_empty(), "{warnings:?}");
+
+        let (actual, _) = rewrite(&ctx, "{{#include a/note.md}}\n", "index.md")?;
+        assert_eq!(actual, "{{#include b/note.md}}\n");
+
+        // Include paths stay relative when links become root absolute.
+        let ctx = RewriteContext {
+            link_style: LinkStyle::RootAbsolute,
+            ..ctx
+        };
+        let content = "{{#include code.rs}} [code](code.rs)\n";
+        let (actual, warnings) = rewrite(&ctx, content, "a/note.md")?;
+        assert_eq!(actual, "{{#include ../a/code.rs}} [code](/a/code.rs)\n");
+        assert!(warnings.is_empty(), "{warnings:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn self_links() -> Result<()> {
+        let (_tmp, root, _) = setup()?;
+        let moves = MoveList::from_iter([(root.join("a/note.md"), root.join("b/renamed.md"))]);
+        let content = "[top](#top) [self](./note.md#top) [self](note.md) [self](/a/note.md)\n";
+        let (actual, warnings) = rewrite(&context(&moves, &root), content, "a/note.md")?;
+        assert_eq!(
+            actual,
+            "[top](#top) [self](renamed.md#top) [self](renamed.md) [self](/b/renamed.md)\n"
+        );
+        assert!(warnings.is_empty(), "{warnings:?}");
+        Ok(())
+    }
+
+    #[test]
+    fn fragments() -> Result<()> {
+        let (_tmp, root, moves) = setup()?;
+        let ctx = context(&moves, &root);
+        fs::write(root.join("index.md"), "# Index\n")?;
+        // The moved file's own links are relative to its new location.
+        let content = "[self](note.md#top) [index](../index.md#sec) [here](#local)\n";
+        let (actual, warnings) = rewrite(&ctx, content, "a/note.md")?;
+        assert_eq!(
+            actual,
+            "[self](note.md#top) [index](../index.md#sec) [here](#local)\n"
+        );
+        assert!(warnings.is_empty());
+
+        let (actual, warnings) = rewrite(&ctx, "[note](a/note.md#top)\n", "index.md")?;
+        assert_eq!(actual, "[note](b/note.md#top)\n");
+        assert!(warnings.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn outside_root() -> Result<()> {
+        let (tmp, root, moves) = setup()?;
+        let outside = tmp.path().canonicalize()?.join("outside.md");
+        fs::write(&outside, "# Outside\n")?;
+        // The root is a subdirectory of the temporary directory.
+        let root = root.join("a");
+        let content = "[outside](/../outside.md)\n";
+        let (actual, warnings) = rewrite(&context(&moves, &root), content, "note.md")?;
+        assert_eq!(actual, content);
+        assert_eq!(
+            warnings,
+            [format!(
+                "'{}' in '{}' is outside of the root",
+                outside.display(),
+                root.join("note.md").display()
+            )]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn verify_links() -> Result<()> {
+        let (_tmp, root, _moves) = setup()?;
+        let target = root.join("b/note.md");
+        let file = root.join("a/other.md");
+        let dir = root.join("a");
+        assert_eq!(
+            verify_link("../b/note.md", &dir, &root, &target, &file),
+            None
+        );
+        assert_eq!(verify_link("/b/note.md", &dir, &root, &target, &file), None);
+        // A relativization that forgot the file's own directory.
+        assert_eq!(
+            verify_link("b/note.md", &dir, &root, &target, &file),
+            Some(format!(
+                "rewritten link 'b/note.md' in '{}' leads to '{}' instead of '{}'",
+                file.display(),
+                root.join("a/b/note.md").display(),
+                target.display(),
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn link_styles() -> Result<()> {
+        let (_tmp, root, moves) = setup()?;
+        let content = "[note](a/note.md) [abs](/a/note.md)\n";
+        let mut ctx = context(&moves, &root);
+        let (actual, warnings) = rewrite(&ctx, content, "index.md")?;
+        assert_eq!(actual, "[note](b/note.md) [abs](/b/note.md)\n");
+        assert!(warnings.is_empty());
+
+        ctx.link_style = "root-absolute".parse()?;
+        let (actual, warnings) = rewrite(&ctx, content, "index.md")?;
+        assert_eq!(actual, "[note](/b/note.md) [abs](/b/note.md)\n");
+        assert!(warnings.is_empty());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_ancestors() -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let tmp = TempDir::new()?;
+        let real = tmp.path().canonicalize()?.join("real");
+        fs::create_dir_all(real.join("vault/notes"))?;
+        fs::write(real.join("vault/a.md"), "# A\n")?;
+        fs::write(real.join("shared.md"), "[a](/a.md)\n")?;
+        // `../shared.md` is resolved from the real vault, not the symlink to it.
+        symlink("../shared.md", real.join("vault/shared.md"))?;
+        let root = tmp.path().join("vault");
+        symlink(real.join("vault"), &root)?;
+
+        let moves = MoveList::from_iter([(root.join("a.md"), root.join("notes/a.md"))]);
+        let ctx = RewriteContext::new(&moves, &root);
+        let plan = plan_moves(&ctx, &ScanOptions::default())?;
+        assert_eq!(
+            plan.rewrites
+                .get(&real.join("shared.md"))
+                .map(String::as_str),
+            Some("[a](/notes/a.md)\n")
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_cycles() -> Result<()> {
+        use std::os::unix::fs::symlink;
+
+        let tmp = TempDir::new()?;
+        let root = tmp.path().canonicalize()?;
+        fs::write(root.join("a.md"), "# A\n")?;
+        fs::write(root.join("b.md"), "[a](a.md)\n")?;
+        symlink("loop.md", root.join("loop.md"))?;
+
+        let moves = MoveList::from_iter([(root.join("a.md"), root.join("c.md"))]);
+        let ctx = RewriteContext::new(&moves, &root);
+        let plan = plan_moves(&ctx, &ScanOptions::default())?;
+        assert_eq!(
+            plan.rewrites.get(&root.join("b.md")).map(String::as_str),
+            Some("[a](c.md)\n")
+        );
+        assert_eq!(
+            plan.warnings,
+            [format!(
+                "skipping {:?}: symlink cycle at {:?}",
+                root.join("loop.md"),
+                root.join("loop.md")
+            )]
+        );
+        Ok(())
+    }
+}
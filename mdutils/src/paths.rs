@@ -0,0 +1,235 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use percent_encoding::percent_decode_str;
+use thiserror::Error;
+
+/// The extensions of markdown files, unless others are given.
+pub const MARKDOWN_EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+/// The most symlinks [`resolve_symlink_chain`] follows.
+pub const MAX_SYMLINK_DEPTH: usize = 40;
+
+/// The ways following a chain of symlinks can fail.
+#[derive(Debug, Error)]
+pub enum SymlinkError {
+    /// The chain leads back to a symlink already followed.
+    #[error("symlink cycle at {0:?}")]
+    Cycle(PathBuf),
+    /// The chain is longer than [`MAX_SYMLINK_DEPTH`].
+    #[error("more than {MAX_SYMLINK_DEPTH} symlinks followed from {0:?}")]
+    TooDeep(PathBuf),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Follows a chain of symlinks, returning the first path which isn't one.
+/// Relative link targets are resolved against the link's real directory,
+/// i.e. with any symlinks in the directory's own path resolved.
+/// A path which isn't a symlink is returned as it is.
+///
+/// Unlike [`Path::canonicalize`], the final path needn't exist.
+pub fn resolve_symlink_chain(path: &Path) -> Result<PathBuf, SymlinkError> {
+    let mut path = path.to_path_buf();
+    let mut visited = HashSet::new();
+    while path.is_symlink() {
+        if !visited.insert(path.clone()) {
+            return Err(SymlinkError::Cycle(path));
+        }
+        if visited.len() > MAX_SYMLINK_DEPTH {
+            return Err(SymlinkError::TooDeep(path));
+        }
+        let target = path.read_link()?;
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        path = normalize_path(&parent.canonicalize()?.join(target));
+    }
+    Ok(path)
+}
+
+/// Returns the canonical path of a symlink's final target,
+/// or `None` if the symlink is dangling.
+pub(crate) fn canonical_symlink_target(path: &Path) -> Result<Option<PathBuf>, SymlinkError> {
+    let target = match resolve_symlink_chain(path) {
+        Err(SymlinkError::Io(err)) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        target => target?,
+    };
+    match target.canonicalize() {
+        Ok(target) => Ok(Some(target)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Lexically normalizes a path, without touching the filesystem.
+/// `.` components are removed and `..` components pop the previous component.
+///
+/// From <https://github.com/rust-lang/cargo/blob/fede83ccf973457de319ba6fa0e36ead454d2e20/src/cargo/util/paths.rs#L61>
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut components = path.components().peekable();
+    let mut ret = if let Some(c @ Component::Prefix(..)) = components.peek().cloned() {
+        components.next();
+        PathBuf::from(c.as_os_str())
+    } else {
+        PathBuf::new()
+    };
+
+    for component in components {
+        match component {
+            Component::Prefix(..) => unreachable!(),
+            Component::RootDir => {
+                ret.push(component.as_os_str());
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                ret.pop();
+            }
+            Component::Normal(c) => {
+                ret.push(c);
+            }
+        }
+    }
+    ret
+}
+
+/// Whether the path has one of the extensions, e.g. `md`.
+pub fn is_markdown(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.contains(&ext))
+}
+
+/// Splits a link into its path and any query or fragment,
+/// e.g. `notes/a.md` and `#intro` for `notes/a.md#intro`.
+pub(crate) fn split_link(link: &str) -> (&str, &str) {
+    link.split_at(link.find(['?', '#']).unwrap_or(link.len()))
+}
+
+/// Returns the absolute path a local link points to,
+/// and whether the link was absolute, i.e. relative to the root.
+/// Any query or fragment is ignored and the path is percent decoded.
+///
+/// Returns `None` for a link with only a query or fragment, e.g. `#intro`.
+pub(crate) fn resolve_link(link: &str, dir: &Path, root: &Path) -> Option<(PathBuf, bool)> {
+    let (link_path, _) = split_link(link);
+    if link_path.is_empty() {
+        return None;
+    }
+    let decoded = percent_decode_str(link_path).decode_utf8_lossy();
+    Some(resolve_link_path(Path::new(decoded.as_ref()), dir, root))
+}
+
+/// Returns the absolute path a decoded link path points to,
+/// and whether the link was absolute, i.e. relative to the root.
+pub(crate) fn resolve_link_path(link_path: &Path, dir: &Path, root: &Path) -> (PathBuf, bool) {
+    let mut comps = link_path.components();
+    let (path, was_abs) = match comps.next() {
+        Some(Component::RootDir) => (root.join(comps.as_path()), true),
+        _ => (dir.join(link_path), false),
+    };
+    (normalize_path(&path), was_abs)
+}
+
+/// Percent decodes the path of a link,
+/// or returns `None` if it isn't valid utf8 once decoded.
+pub(crate) fn decode_link_path(link_path: &str) -> Option<Cow<'_, str>> {
+    percent_decode_str(link_path).decode_utf8().ok()
+}
+
+/// Percent encodes the characters which can't appear in the path of a link.
+/// Other characters, including non-ascii ones, are left as they are.
+pub(crate) fn encode_link_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for c in path.chars() {
+        if matches!(c, ' ' | '%' | '<' | '>' | '?' | '#') || c.is_ascii_control() {
+            // Writing to a string can't fail.
+            write!(encoded, "%{:02X}", c as u8).unwrap();
+        } else {
+            encoded.push(c);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parent_dirs() {
+        assert_eq!(
+            normalize_path(Path::new("/a/b/../c/./d/../../e.md")),
+            Path::new("/a/e.md")
+        );
+        assert_eq!(normalize_path(Path::new("a/../../b")), Path::new("b"));
+    }
+
+    #[test]
+    fn current_dirs() {
+        assert_eq!(normalize_path(Path::new("./a/./b.md")), Path::new("a/b.md"));
+        assert_eq!(normalize_path(Path::new(".")), Path::new(""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_chains() -> Result<(), SymlinkError> {
+        use std::os::unix::fs::symlink;
+
+        let tmp = tempfile::TempDir::new()?;
+        let dir = tmp.path();
+        std::fs::write(dir.join("file.md"), "# File\n")?;
+        assert_eq!(
+            resolve_symlink_chain(&dir.join("file.md"))?,
+            dir.join("file.md")
+        );
+
+        symlink("file.md", dir.join("link.md"))?;
+        assert_eq!(
+            resolve_symlink_chain(&dir.join("link.md"))?,
+            dir.join("file.md")
+        );
+
+        std::fs::create_dir(dir.join("sub"))?;
+        symlink("../link.md", dir.join("sub/chain.md"))?;
+        assert_eq!(
+            resolve_symlink_chain(&dir.join("sub/chain.md"))?,
+            dir.join("file.md")
+        );
+
+        symlink("b.md", dir.join("a.md"))?;
+        symlink("a.md", dir.join("b.md"))?;
+        assert!(matches!(
+            resolve_symlink_chain(&dir.join("a.md")),
+            Err(SymlinkError::Cycle(path)) if path == dir.join("a.md")
+        ));
+
+        // `..` is taken from the real directory, not the symlink to it.
+        std::fs::create_dir(dir.join("sub/deep"))?;
+        std::fs::write(dir.join("sub/deep.md"), "# Deep\n")?;
+        symlink("sub/deep", dir.join("alias"))?;
+        symlink("../deep.md", dir.join("sub/deep/up.md"))?;
+        assert_eq!(
+            resolve_symlink_chain(&dir.join("alias/up.md"))?,
+            dir.join("sub/deep.md")
+        );
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_prefix() {
+        assert_eq!(
+            normalize_path(Path::new(r"C:\a\..\b\.\c.md")),
+            Path::new(r"C:\b\c.md")
+        );
+        assert_eq!(
+            normalize_path(Path::new(r"\\server\share\a\..\b")),
+            Path::new(r"\\server\share\b")
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use core::ops::Range;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Error, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::links::{get_links, is_local_link};
+use crate::paths::{
+    canonical_symlink_target, is_markdown, resolve_link, SymlinkError, MARKDOWN_EXTENSIONS,
+};
+
+/// Whether a link leads anywhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkStatus {
+    /// A local link to a path which exists.
+    Internal,
+    /// A local link to a path which doesn't exist.
+    Broken,
+    /// A link with a scheme or a protocol relative link,
+    /// which isn't checked.
+    External,
+}
+
+/// An outbound link found by [`links_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkReportEntry {
+    /// The link as written.
+    pub link: String,
+    /// The byte range of the link in the file.
+    pub range: Range<usize>,
+    pub status: LinkStatus,
+    /// The path a local link resolves to, without any query or fragment.
+    pub path: Option<PathBuf>,
+}
+
+/// The result of [`links_report`] or [`links_report_since`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinksReport {
+    /// The outbound links of each markdown file,
+    /// keyed by the file's path and in document order.
+    pub files: HashMap<PathBuf, Vec<LinkReportEntry>>,
+    /// Problems with files which were skipped, e.g. symlink cycles.
+    pub warnings: Vec<String>,
+}
+
+/// Returns the outbound links of every markdown file under `root`,
+/// keyed by the file's path and in document order.
+///
+/// Relative links are resolved against the directory of the file containing
+/// them and absolute links, e.g. `/notes/foo.md`, against the root.
+/// Hidden files and directories, e.g. `.git`, are skipped.
+/// Symlinks are followed, unless they lead back to a directory containing them.
+/// Dangling symlinks are skipped, as are symlink cycles, with a warning.
+pub fn links_report(root: &Path) -> Result<LinksReport> {
+    let mut report = LinksReport::default();
+    add_dir_to_report(root, root, &mut HashSet::new(), &mut report)?;
+    Ok(report)
+}
+
+/// Like [`links_report`], but only reports the markdown files under `root`
+/// which differ from the git revision `base`, e.g. `origin/main`,
+/// according to `git diff`.
+/// Links are still resolved against the whole tree.
+///
+/// Untracked files, which `git diff` doesn't list, and deleted files are skipped.
+/// Errors if `root` isn't in a git repository or `base` isn't a revision.
+pub fn links_report_since(root: &Path, base: &str) -> Result<LinksReport> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        // `-z` stops git quoting paths with non-ascii characters.
+        .args(["diff", "--name-only", "-z", "--relative", base, "--"])
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::other(format!(
+            "git diff failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let mut report = LinksReport::default();
+    let names = String::from_utf8_lossy(&output.stdout);
+    for name in names.split('\0').filter(|name| !name.is_empty()) {
+        let path = root.join(name);
+        if is_markdown(&path, &MARKDOWN_EXTENSIONS) && path.is_file() {
+            let entries = file_report(&path, root)?;
+            report.files.insert(path, entries);
+        }
+    }
+    Ok(report)
+}
+
+/// `ancestors` holds the canonical paths of the directories containing `dir`.
+fn add_dir_to_report(
+    dir: &Path,
+    root: &Path,
+    ancestors: &mut HashSet<PathBuf>,
+    report: &mut LinksReport,
+) -> Result<()> {
+    let canonical = dir.canonicalize()?;
+    if !ancestors.insert(canonical.clone()) {
+        // A symlink back to an ancestor would be followed forever.
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_hidden = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'));
+        if is_hidden {
+            continue;
+        }
+        // Files are reported under the path of any symlink to them.
+        let real_path = if path.is_symlink() {
+            match canonical_symlink_target(&path) {
+                Ok(Some(target)) => target,
+                Ok(None) => continue,
+                Err(SymlinkError::Io(err)) => return Err(err),
+                Err(err) => {
+                    report.warnings.push(format!("skipping {path:?}: {err}"));
+                    continue;
+                }
+            }
+        } else {
+            path.clone()
+        };
+        if real_path.is_dir() {
+            add_dir_to_report(&path, root, ancestors, report)?;
+        } else if is_markdown(&path, &MARKDOWN_EXTENSIONS) {
+            let entries = file_report(&path, root)?;
+            report.files.insert(path, entries);
+        }
+    }
+    ancestors.remove(&canonical);
+    Ok(())
+}
+
+fn file_report(file: &Path, root: &Path) -> Result<Vec<LinkReportEntry>> {
+    let content = fs::read_to_string(file)?;
+    // unwrap safe because the file was found in a directory
+    let dir = file.parent().unwrap();
+    let mut links = get_links(&content);
+    links.sort_by_key(|range| range.start);
+    let entries = links
+        .into_iter()
+        .map(|range| {
+            let link = &content[range.clone()];
+            let (status, path) = if is_local_link(link) {
+                let path = match resolve_link(link, dir, root) {
+                    Some((path, _)) => path,
+                    // A link to a fragment of the file itself, e.g. `#foo`.
+                    None => file.to_path_buf(),
+                };
+                let status = if path.exists() {
+                    LinkStatus::Internal
+                } else {
+                    LinkStatus::Broken
+                };
+                (status, Some(path))
+            } else {
+                (LinkStatus::External, None)
+            };
+            LinkReportEntry {
+                link: link.to_string(),
+                range,
+                status,
+                path,
+            }
+        })
+        .collect();
+    Ok(entries)
+}
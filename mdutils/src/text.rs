@@ -0,0 +1,240 @@
+use core::ops::Range;
+
+use once_cell::sync::Lazy;
+use tree_sitter::{Node, Query, QueryCursor};
+
+static INLINE_QUERY: Lazy<Query> =
+    Lazy::new(|| Query::new(&tree_sitter_md::language(), "(inline) @inline").unwrap());
+static CODE_BLOCK_QUERY: Lazy<Query> = Lazy::new(|| {
+    Query::new(
+        &tree_sitter_md::language(),
+        "(fenced_code_block
+            (info_string (language) @language)?
+            (code_fence_content) @content
+        )",
+    )
+    .unwrap()
+});
+
+/// Inline nodes which aren't prose, along with everything inside them.
+const NON_TEXT: [&str; 9] = [
+    "code_span",
+    "latex_block",
+    "html_tag",
+    "uri_autolink",
+    "email_autolink",
+    "link_destination",
+    "link_title",
+    "link_label",
+    "emphasis_delimiter",
+];
+/// Inline nodes whose punctuation, e.g. `[`, `](` and `)`, is markup.
+const LINKS: [&str; 5] = [
+    "inline_link",
+    "image",
+    "full_reference_link",
+    "collapsed_reference_link",
+    "shortcut_link",
+];
+
+/// Returns the byte range of every span of prose in the input markdown,
+/// in document order.
+///
+/// This is the dual of [`get_links`](crate::links::get_links).
+/// Code blocks, code spans, maths, raw HTML, link destinations and markup,
+/// such as emphasis delimiters and link brackets, are excluded.
+/// The text of links, e.g. `foo` in `[foo](foo.md)`, is included.
+/// Spans containing only whitespace are skipped.
+pub fn get_text_spans(input: &str) -> Vec<Range<usize>> {
+    let tree = crate::parse_tree(input);
+
+    let mut spans = Vec::new();
+    let mut query_cur = QueryCursor::new();
+    let matches = query_cur.matches(
+        &INLINE_QUERY,
+        tree.block_tree().root_node(),
+        input.as_bytes(),
+    );
+    for inline in matches.flat_map(|matches| matches.captures.iter().map(|c| c.node)) {
+        // Block quote markers and indentation continuing the block.
+        let mut excluded: Vec<_> = children(inline)
+            .filter(|child| child.kind() == "block_continuation")
+            .map(|child| child.byte_range())
+            .collect();
+        if let Some(inline_tree) = tree.inline_tree(&inline) {
+            collect_markup(inline_tree.root_node(), &mut excluded);
+        }
+        excluded.sort_by_key(|range| range.start);
+
+        // The spans are the gaps between the excluded ranges.
+        let mut cursor = inline.start_byte();
+        let mut push_span = |start: usize, end: usize| {
+            if start < end && !input[start..end].trim().is_empty() {
+                spans.push(start..end);
+            }
+        };
+        for range in excluded {
+            push_span(cursor, range.start);
+            cursor = cursor.max(range.end);
+        }
+        push_span(cursor, inline.end_byte());
+    }
+    spans
+}
+
+/// A fenced code block found by [`get_code_blocks`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// The first word of the info string, e.g. `toml` in ```` ```toml ````.
+    pub language: Option<String>,
+    /// The byte ranges of the code, excluding the fences and the markers
+    /// of any block quote the code block is in, e.g. `> `.
+    pub spans: Vec<Range<usize>>,
+}
+
+/// Returns every non-empty fenced code block in the input markdown,
+/// in document order.
+/// Indented code blocks, which can't have a language, aren't returned.
+pub fn get_code_blocks(input: &str) -> Vec<CodeBlock> {
+    let tree = crate::parse_tree(input);
+    let language_idx = CODE_BLOCK_QUERY.capture_index_for_name("language").unwrap();
+    let content_idx = CODE_BLOCK_QUERY.capture_index_for_name("content").unwrap();
+
+    let mut blocks = Vec::new();
+    let mut query_cur = QueryCursor::new();
+    let matches = query_cur.matches(
+        &CODE_BLOCK_QUERY,
+        tree.block_tree().root_node(),
+        input.as_bytes(),
+    );
+    for block in matches {
+        let node = |idx| {
+            block
+                .captures
+                .iter()
+                .find(|capture| capture.index == idx)
+                .map(|capture| capture.node)
+        };
+        let Some(content) = node(content_idx) else {
+            continue;
+        };
+        let language = node(language_idx).map(|language| input[language.byte_range()].to_string());
+        // The spans are the gaps between the block continuations.
+        let mut spans = Vec::new();
+        let mut cursor = content.start_byte();
+        for continuation in children(content).filter(|child| child.kind() == "block_continuation") {
+            if cursor < continuation.start_byte() {
+                spans.push(cursor..continuation.start_byte());
+            }
+            cursor = cursor.max(continuation.end_byte());
+        }
+        if cursor < content.end_byte() {
+            spans.push(cursor..content.end_byte());
+        }
+        blocks.push(CodeBlock { language, spans });
+    }
+    blocks
+}
+
+fn collect_markup(node: Node, excluded: &mut Vec<Range<usize>>) {
+    let is_link = LINKS.contains(&node.kind());
+    for child in children(node) {
+        if NON_TEXT.contains(&child.kind()) || (is_link && !child.is_named()) {
+            excluded.push(child.byte_range());
+        } else {
+            collect_markup(child, excluded);
+        }
+    }
+}
+
+fn children(node: Node) -> impl Iterator<Item = Node> {
+    (0..node.child_count()).filter_map(move |idx| node.child(idx))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text_spans(input: &str) -> Vec<&str> {
+        get_text_spans(input)
+            .into_iter()
+            .map(|range| &input[range])
+            .collect()
+    }
+
+    #[test]
+    fn prose() {
+        let input = "\
+# The *title*
+
+Some `code`, $x$ and [a link](a.md \"title\").
+
+> quoted
+> text
+
+- an item
+  continued
+
+```
+# not prose
+```
+";
+        assert_eq!(
+            text_spans(input),
+            [
+                "The ",
+                "title",
+                "Some ",
+                ", ",
+                " and ",
+                "a link",
+                ".",
+                "quoted\n",
+                "text",
+                "an item\n",
+                "continued",
+            ]
+        );
+    }
+
+    #[test]
+    fn code_blocks() {
+        let input = "\
+```toml title=\"Cargo.toml\"
+version = \"1\"
+```
+
+> ```rust
+> let v = \"1\";
+> let w = v;
+> ```
+
+```
+plain
+```
+
+```text
+```
+
+    indented
+";
+        let blocks: Vec<_> = get_code_blocks(input)
+            .into_iter()
+            .map(|block| {
+                let spans: Vec<_> = block.spans.into_iter().map(|span| &input[span]).collect();
+                (block.language, spans)
+            })
+            .collect();
+        assert_eq!(
+            blocks,
+            [
+                (Some("toml".to_string()), vec!["version = \"1\"\n"]),
+                (
+                    Some("rust".to_string()),
+                    vec!["let v = \"1\";\n", "let w = v;\n"]
+                ),
+                (None, vec!["plain\n"]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use mdutils::report::{links_report, links_report_since, LinkReportEntry, LinkStatus};
+
+fn fixture() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/report")
+}
+
+/// The link, status and path of each entry,
+/// with the path relative to the fixture.
+fn summarize(entries: &[LinkReportEntry]) -> Vec<(&str, LinkStatus, Option<&Path>)> {
+    let root = fixture();
+    entries
+        .iter()
+        .map(|entry| {
+            let path = entry
+                .path
+                .as_deref()
+                .map(|path| path.strip_prefix(&root).unwrap());
+            (entry.link.as_str(), entry.status, path)
+        })
+        .collect()
+}
+
+#[test]
+fn report_files() {
+    let root = fixture();
+    let report = links_report(&root).unwrap().files;
+    let mut files: Vec<_> = report
+        .keys()
+        .map(|path| path.strip_prefix(&root).unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [
+            Path::new("index.md"),
+            Path::new("notes/a.md"),
+            Path::new("notes/b note.md"),
+        ]
+    );
+}
+
+#[test]
+fn link_statuses() {
+    let root = fixture();
+    let report = links_report(&root).unwrap().files;
+
+    assert_eq!(
+        summarize(&report[&root.join("index.md")]),
+        [
+            (
+                "notes/a.md",
+                LinkStatus::Internal,
+                Some(Path::new("notes/a.md"))
+            ),
+            (
+                "notes/b%20note.md#top",
+                LinkStatus::Internal,
+                Some(Path::new("notes/b note.md"))
+            ),
+            (
+                "missing.md",
+                LinkStatus::Broken,
+                Some(Path::new("missing.md"))
+            ),
+            ("https://hugom.uk", LinkStatus::External, None),
+            (
+                "/index.md",
+                LinkStatus::Internal,
+                Some(Path::new("index.md"))
+            ),
+        ]
+    );
+    assert_eq!(
+        summarize(&report[&root.join("notes/a.md")]),
+        [
+            (
+                "../index.md",
+                LinkStatus::Internal,
+                Some(Path::new("index.md"))
+            ),
+            ("#a", LinkStatus::Internal, Some(Path::new("notes/a.md"))),
+            ("mailto:me@hugom.uk", LinkStatus::External, None),
+            (
+                "../images/missing.png",
+                LinkStatus::Broken,
+                Some(Path::new("images/missing.png"))
+            ),
+        ]
+    );
+    assert_eq!(
+        summarize(&report[&root.join("notes/b note.md")]),
+        [(
+            "/notes/c.md",
+            LinkStatus::Broken,
+            Some(Path::new("notes/c.md"))
+        )]
+    );
+}
+
+#[test]
+fn entry_ranges() {
+    let root = fixture();
+    let report = links_report(&root).unwrap().files;
+    let file = root.join("notes/a.md");
+    let content = std::fs::read_to_string(&file).unwrap();
+    for entry in &report[&file] {
+        assert_eq!(content[entry.range.clone()], entry.link);
+    }
+}
+
+#[test]
+fn changed_files() {
+    let tmp = tempfile::TempDir::new().unwrap();
+    let root = tmp.path().canonicalize().unwrap();
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(&root)
+            .args(["-c", "user.name=test", "-c", "user.email=test@test"])
+            .args(args)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+    fs::create_dir(root.join("notes")).unwrap();
+    fs::write(root.join("index.md"), "[a](notes/a.md)\n").unwrap();
+    fs::write(root.join("notes/a.md"), "# A\n").unwrap();
+    fs::write(root.join("notes/b.md"), "# B\n").unwrap();
+    fs::write(root.join("notes/café.md"), "# Café\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-q", "-m", "init"]);
+
+    fs::write(root.join("notes/b.md"), "[a](a.md) [c](c.md)\n").unwrap();
+    fs::write(root.join("notes/untracked.md"), "[x](x.md)\n").unwrap();
+    let report = links_report_since(&root, "HEAD").unwrap().files;
+    let files: Vec<_> = report.keys().collect();
+    assert_eq!(files, [&root.join("notes/b.md")]);
+
+    // Paths with non-ascii characters aren't quoted.
+    fs::write(root.join("notes/café.md"), "[b](b.md)\n").unwrap();
+    let report = links_report_since(&root, "HEAD").unwrap().files;
+    let mut files: Vec<_> = report.keys().collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [&root.join("notes/b.md"), &root.join("notes/café.md")]
+    );
+    fs::write(root.join("notes/café.md"), "# Café\n").unwrap();
+    let statuses: Vec<_> = report[&root.join("notes/b.md")]
+        .iter()
+        .map(|entry| (entry.link.as_str(), entry.status))
+        .collect();
+    assert_eq!(
+        statuses,
+        [("a.md", LinkStatus::Internal), ("c.md", LinkStatus::Broken)]
+    );
+
+    // Only the changes under the root are considered.
+    let report = links_report_since(&root.join("notes"), "HEAD")
+        .unwrap()
+        .files;
+    assert_eq!(report.len(), 1);
+
+    assert!(links_report_since(&root, "no-such-revision").is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_cycles() {
+    use std::os::unix::fs::symlink;
+
+    let tmp = tempfile::TempDir::new().unwrap();
+    let root = tmp.path().canonicalize().unwrap();
+    fs::create_dir(root.join("notes")).unwrap();
+    fs::write(root.join("notes/a.md"), "[index](../index.md)\n").unwrap();
+    fs::write(root.join("index.md"), "# Index\n").unwrap();
+    symlink("..", root.join("notes/up")).unwrap();
+    symlink("notes", root.join("alias")).unwrap();
+
+    let report = links_report(&root).unwrap().files;
+    let mut files: Vec<_> = report
+        .keys()
+        .map(|path| path.strip_prefix(&root).unwrap())
+        .collect();
+    files.sort();
+    // The alias isn't an ancestor of itself, so it's followed.
+    assert_eq!(
+        files,
+        [
+            Path::new("alias/a.md"),
+            Path::new("index.md"),
+            Path::new("notes/a.md"),
+        ]
+    );
+
+    // A cycle is skipped with a warning, and the rest is still reported.
+    symlink("loop.md", root.join("loop.md")).unwrap();
+    let report = links_report(&root).unwrap();
+    assert_eq!(report.files.len(), 3);
+    assert_eq!(
+        report.warnings,
+        [format!(
+            "skipping {:?}: symlink cycle at {:?}",
+            root.join("loop.md"),
+            root.join("loop.md")
+        )]
+    );
+}